@@ -0,0 +1,58 @@
+use std::collections::hash_map::{Entry, HashMap};
+use std::hash::Hash;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    // The step at which the simulation said it was done
+    Stable(usize),
+    // The state after step `start` comes back every `period` steps, and never finishes
+    Cycle { start: usize, period: usize },
+    // Neither happened within this many steps
+    Limit(usize),
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Stable(step) => write!(f, "{}", step),
+            Outcome::Cycle { start, period } => write!(f, "cycle from step {} every {} steps", start, period),
+            Outcome::Limit(steps) => write!(f, "undecided after {} steps", steps),
+        }
+    }
+}
+
+// Steps a simulation (the step function returns true once it is done) and remembers every state
+// it went through, as seen by `key`. Step 0 is the initial state.
+pub fn run<S, K: Hash + Eq>(
+    state: &mut S,
+    max_steps: usize,
+    key: impl Fn(&S) -> K,
+    mut step: impl FnMut(&mut S) -> bool
+) -> Outcome {
+    let mut seen = HashMap::new();
+    seen.insert(key(state), 0);
+    for n in 1..=max_steps {
+        if step(state) {
+            return Outcome::Stable(n)
+        }
+        match seen.entry(key(state)) {
+            Entry::Occupied(entry) => return Outcome::Cycle { start: *entry.get(), period: n - entry.get() },
+            Entry::Vacant(entry) => { entry.insert(n); }
+        }
+    }
+    Outcome::Limit(max_steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable_cycle_and_limit() {
+        assert_eq!(run(&mut 0, 10, |&n| n, |n| { *n += 1; *n == 4 }), Outcome::Stable(4));
+        // 3, 4, 5, 6, 7, 3, ...
+        assert_eq!(run(&mut 0, 100, |&n| n, |n| { *n = if *n == 7 { 3 } else { *n + 1 }; false }),
+            Outcome::Cycle { start: 3, period: 5 });
+        assert_eq!(run(&mut 0, 10, |&n| n, |n| { *n += 1; false }), Outcome::Limit(10));
+    }
+}
@@ -1,78 +1,415 @@
-
 #[aoc_generator(day10)]
 fn generator(input: &str) -> Vec<Vec<char>> {
     input.split("\n").map(|l| l.chars().collect()).collect()
 }
 
-#[aoc(day10, part1)]
-fn corrupt(input: &[Vec<char>]) -> usize {
-    input
-        .iter()
-        .map(move |chunk| {
-            let mut stack = Vec::new();
-            for &c in chunk {
-                match c {
-                    '(' => stack.push(')'),
-                    '[' => stack.push(']'),
-                    '{' => stack.push('}'),
-                    '<' => stack.push('>'),
-                    closing => {
-                        match stack.pop() {
-                            Some(expected) => if expected != closing {
-                                return match closing {
-                                    ')' => 3,
-                                    ']' => 57,
-                                    '}' => 1197,
-                                    '>' => 25137,
-                                    _ => unreachable!()
-                                }
-                            },
-                            None => unreachable!()
-                        }
+// Columns are counted from 0
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Diagnostic {
+    Valid,
+    // Every bracket so far matched, but some are still open
+    Incomplete { completion: String },
+    // A closer that doesn't match the innermost open bracket
+    Mismatched { column: usize, expected: char, found: char },
+    // A closer with nothing open
+    Unmatched { column: usize, found: char },
+    // Neither an opener nor a closer
+    Unexpected { column: usize, found: char },
+}
+
+struct BracketChecker {
+    pairs: Vec<(char, char)>
+}
+
+impl BracketChecker {
+    fn new(pairs: &[(char, char)]) -> Self {
+        Self { pairs: pairs.to_vec() }
+    }
+
+    fn aoc() -> Self {
+        Self::new(&[('(', ')'), ('[', ']'), ('{', '}'), ('<', '>')])
+    }
+
+    // The stack of closers still expected, or the first error
+    fn scan(&self, line: &[char]) -> Result<Vec<char>, Diagnostic> {
+        let mut stack = Vec::new();
+        for (column, &c) in line.iter().enumerate() {
+            if let Some(&(_, close)) = self.pairs.iter().find(|&&(open, _)| open == c) {
+                stack.push(close);
+            }
+            else if self.pairs.iter().any(|&(_, close)| close == c) {
+                match stack.pop() {
+                    Some(expected) => if expected != c {
+                        return Err(Diagnostic::Mismatched { column, expected, found: c })
+                    },
+                    None => return Err(Diagnostic::Unmatched { column, found: c })
+                }
+            }
+            else {
+                return Err(Diagnostic::Unexpected { column, found: c })
+            }
+        }
+        Ok(stack)
+    }
+
+    fn check(&self, line: &[char]) -> Diagnostic {
+        match self.scan(line) {
+            Ok(stack) if stack.is_empty() => Diagnostic::Valid,
+            Ok(stack) => Diagnostic::Incomplete { completion: stack.iter().rev().collect() },
+            Err(diagnostic) => diagnostic
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Edit {
+    Substitute { column: usize, found: char, replacement: char },
+    Delete { column: usize, found: char },
+}
+
+// The fewest edits that turn a line into a valid prefix, plus the closers that finish it off
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Repair {
+    edits: Vec<Edit>,
+    completion: String,
+    corrected: String,
+}
+
+#[derive(Clone, Copy)]
+enum Choice {
+    Empty,
+    Delete,
+    Open,
+    // Matched with the closer at this index, as this pair
+    Pair(usize, usize),
+}
+
+impl BracketChecker {
+    // Cheapest way to make line[i] and line[k] a pair, and which pair
+    fn pair_cost(&self, first: char, second: char) -> (usize, usize) {
+        self.pairs
+            .iter()
+            .enumerate()
+            .map(|(t, &(open, close))| ((first != open) as usize + (second != close) as usize, t))
+            .min()
+            .unwrap()
+    }
+
+    // Edit distance to a valid prefix, allowing substitutions and deletions. Any valid prefix
+    // is a run of balanced groups and unclosed openers, and a balanced group is an opener,
+    // a balanced group, its closer, and another balanced group. So we compute the cost of
+    // making every substring balanced (O(n^3)), then the cost of making every suffix a prefix.
+    fn repair(&self, line: &[char]) -> Repair {
+        let n = line.len();
+        let mut balanced = vec![vec![(0, Choice::Empty); n + 1]; n + 1];
+        for len in 1..=n {
+            for i in 0..=n - len {
+                let j = i + len;
+                let mut best = (1 + balanced[i + 1][j].0, Choice::Delete);
+                for k in i + 1..j {
+                    let (cost, t) = self.pair_cost(line[i], line[k]);
+                    let cost = cost + balanced[i + 1][k].0 + balanced[k + 1][j].0;
+                    if cost < best.0 {
+                        best = (cost, Choice::Pair(k, t));
                     }
                 }
+                balanced[i][j] = best;
+            }
+        }
+        let mut prefix = vec![(0, Choice::Empty); n + 1];
+        for i in (0..n).rev() {
+            let mut best = (1 + prefix[i + 1].0, Choice::Delete);
+            if self.pairs.iter().any(|&(open, _)| open == line[i]) && prefix[i + 1].0 < best.0 {
+                best = (prefix[i + 1].0, Choice::Open);
+            }
+            for k in i + 1..n {
+                let (cost, t) = self.pair_cost(line[i], line[k]);
+                let cost = cost + balanced[i + 1][k].0 + prefix[k + 1].0;
+                if cost < best.0 {
+                    best = (cost, Choice::Pair(k, t));
+                }
             }
-            0
-        })
+            prefix[i] = best;
+        }
+
+        // Walk the choices back, keeping track of what every character becomes
+        let mut replaced: Vec<Option<char>> = line.iter().map(|&c| Some(c)).collect();
+        let mut pending = vec![(0, n, true)];
+        while let Some((i, j, is_prefix)) = pending.pop() {
+            if i >= j {
+                continue;
+            }
+            let choice = if is_prefix { prefix[i].1 } else { balanced[i][j].1 };
+            match choice {
+                Choice::Empty => (),
+                Choice::Delete => {
+                    replaced[i] = None;
+                    pending.push((i + 1, j, is_prefix));
+                },
+                Choice::Open => pending.push((i + 1, j, is_prefix)),
+                Choice::Pair(k, t) => {
+                    let (open, close) = self.pairs[t];
+                    replaced[i] = Some(open);
+                    replaced[k] = Some(close);
+                    pending.push((i + 1, k, false));
+                    pending.push((k + 1, j, is_prefix));
+                },
+            }
+        }
+        let edits = line
+            .iter()
+            .zip(&replaced)
+            .enumerate()
+            .filter_map(|(column, (&found, &replacement))| match replacement {
+                None => Some(Edit::Delete { column, found }),
+                Some(replacement) if replacement != found => Some(Edit::Substitute { column, found, replacement }),
+                _ => None
+            })
+            .collect();
+        let fixed: Vec<char> = replaced.into_iter().flatten().collect();
+        let completion: String = match self.check(&fixed) {
+            Diagnostic::Incomplete { completion } => completion,
+            _ => String::new()
+        };
+        let corrected = fixed.iter().collect::<String>() + &completion;
+        Repair { edits, completion, corrected }
+    }
+}
+
+trait Scoring {
+    // Score of a line, or None if this kind of line isn't scored at all
+    fn score(&self, diagnostic: &Diagnostic) -> Option<usize>;
+}
+
+// Points for the first illegal closer, or base 5 points for each missing closer
+struct AocScoring;
+
+impl Scoring for AocScoring {
+    fn score(&self, diagnostic: &Diagnostic) -> Option<usize> {
+        let points = |c| match c {
+            ')' => Some(1),
+            ']' => Some(2),
+            '}' => Some(3),
+            '>' => Some(4),
+            _ => None
+        };
+        match diagnostic {
+            Diagnostic::Valid => None,
+            Diagnostic::Incomplete { completion } => completion
+                .chars()
+                .try_fold(0, |n, c| Some(n * 5 + points(c)?)),
+            Diagnostic::Mismatched { found, .. } | Diagnostic::Unmatched { found, .. } => match found {
+                ')' => Some(3),
+                ']' => Some(57),
+                '}' => Some(1197),
+                '>' => Some(25137),
+                _ => None
+            },
+            Diagnostic::Unexpected { .. } => None,
+        }
+    }
+}
+
+// How much editing a line needs: one per error, one per missing closer
+struct EditScoring;
+
+impl Scoring for EditScoring {
+    fn score(&self, diagnostic: &Diagnostic) -> Option<usize> {
+        match diagnostic {
+            Diagnostic::Valid => Some(0),
+            Diagnostic::Incomplete { completion } => Some(completion.chars().count()),
+            _ => Some(1)
+        }
+    }
+}
+
+fn is_corrupt(diagnostic: &Diagnostic) -> bool {
+    !matches!(diagnostic, Diagnostic::Valid | Diagnostic::Incomplete { .. })
+}
+
+fn corruption_score(input: &[Vec<char>], checker: &BracketChecker, scoring: &dyn Scoring) -> usize {
+    input
+        .iter()
+        .map(|line| checker.check(line))
+        .filter(is_corrupt)
+        .filter_map(|diagnostic| scoring.score(&diagnostic))
         .sum()
 }
+
+// The middle score, if any line is incomplete
+fn completion_score(input: &[Vec<char>], checker: &BracketChecker, scoring: &dyn Scoring) -> Option<usize> {
+    let mut scores: Vec<_> = input
+        .iter()
+        .map(|line| checker.check(line))
+        .filter(|diagnostic| matches!(diagnostic, Diagnostic::Incomplete { .. }))
+        .filter_map(|diagnostic| scoring.score(&diagnostic))
+        .collect();
+    scores.sort_unstable();
+    scores.get(scores.len() / 2).copied()
+}
+
+#[aoc(day10, part1)]
+fn corrupt(input: &[Vec<char>]) -> usize {
+    corruption_score(input, &BracketChecker::aoc(), &AocScoring)
+}
+
 #[aoc(day10, part2)]
-fn incomplete(input: &[Vec<char>]) -> usize {
+fn incomplete(input: &[Vec<char>]) -> Option<usize> {
+    completion_score(input, &BracketChecker::aoc(), &AocScoring)
+}
+
+#[aoc(day10, part1, Edits)]
+fn corrupt_edits(input: &[Vec<char>]) -> usize {
+    corruption_score(input, &BracketChecker::aoc(), &EditScoring)
+}
+
+#[aoc(day10, part2, Edits)]
+fn incomplete_edits(input: &[Vec<char>]) -> Option<usize> {
+    completion_score(input, &BracketChecker::aoc(), &EditScoring)
+}
+
+#[aoc(day10, part1, Repair)]
+fn repair_edits(input: &[Vec<char>]) -> usize {
+    let checker = BracketChecker::aoc();
+    input.iter().map(|line| checker.repair(line).edits.len()).sum()
+}
+
+#[aoc(day10, part2, Repair)]
+fn repair_completions(input: &[Vec<char>]) -> Option<usize> {
+    let checker = BracketChecker::aoc();
     let mut scores: Vec<_> = input
         .iter()
-        .filter_map(move |chunk| {
-            let mut stack = Vec::new();
-            for &c in chunk {
-                match c {
-                    '(' => stack.push(')'),
-                    '[' => stack.push(']'),
-                    '{' => stack.push('}'),
-                    '<' => stack.push('>'),
-                    closing => {
-                        match stack.pop() {
-                            Some(expected) => if expected != closing {
-                                return None
-                            },
-                            None => unreachable!()
-                        }
-                    }
-                }
-            }
-            Some(stack
-                .iter()
-                .rev()
-                .fold(0, |n, &c| n * 5 + match c {
-                    ')' => 1,
-                    ']' => 2,
-                    '}' => 3,
-                    '>' => 4,
-                    _ => unreachable!()
-                })
-            )
-        })
+        .map(|line| checker.repair(line))
+        .filter(|repair| repair.edits.is_empty() && !repair.completion.is_empty())
+        .filter_map(|repair| AocScoring.score(&Diagnostic::Incomplete { completion: repair.completion }))
         .collect();
     scores.sort_unstable();
-    dbg!(scores.len());
-    scores[scores.len() / 2]
+    scores.get(scores.len() / 2).copied()
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+[({(<(())[]>[[{[]{<()<>>
+[(()[<>])]({[<{<<[]>>(
+{([(<{}[<>[]}>{[]{[(<()>
+(((({<>}<{<{<>}{[]{[]{}
+[[<[([]))<([[{}[[()]]]
+[{[{({}]{}}([{[{{{}}([]
+{<[[]]>}<{[{[{[]{()[[[]
+[<(<(<(<{}))><([]([]()
+<{([([[(<>()){}]>(<<{{
+<{([{{}}[<[[[<>{}]]]>[]]";
+
+    #[test]
+    fn scores_match_the_example() {
+        let input = generator(EXAMPLE);
+        assert_eq!(corrupt(&input), 26397);
+        assert_eq!(incomplete(&input), Some(288957));
+        assert_eq!(corrupt_edits(&input), 5);
+        assert_eq!(incomplete_edits(&input), Some(8));
+    }
+
+    #[test]
+    fn diagnostics() {
+        let checker = BracketChecker::aoc();
+        let check = |line: &str| checker.check(&line.chars().collect::<Vec<_>>());
+        assert_eq!(check("([]<>)"), Diagnostic::Valid);
+        assert_eq!(check("{([(<{}[<>[]}>{[]{[(<()>"), Diagnostic::Mismatched { column: 12, expected: ']', found: '}' });
+        assert_eq!(check("[({(<(())[]>[[{[]{<()<>>"), Diagnostic::Incomplete { completion: "}}]])})]".to_string() });
+        assert_eq!(check("())"), Diagnostic::Unmatched { column: 2, found: ')' });
+        assert_eq!(check("(x)"), Diagnostic::Unexpected { column: 1, found: 'x' });
+    }
+
+    #[test]
+    fn nothing_incomplete_has_no_middle_score() {
+        let input = generator("()\n{[}]");
+        assert_eq!(incomplete(&input), None);
+        assert_eq!(repair_completions(&input), None);
+        assert_eq!(repair_completions(&generator("(]")), None);
+    }
+
+    #[test]
+    fn valid_lines_have_no_completion_score() {
+        let input = generator("()\n()\n(((");
+        assert_eq!(incomplete(&input), Some(31));
+        assert_eq!(repair_completions(&input), Some(31));
+    }
+
+    #[test]
+    fn repairs_match_the_example() {
+        let input = generator(EXAMPLE);
+        let checker = BracketChecker::aoc();
+        for line in &input {
+            let repair = checker.repair(line);
+            let corrected: Vec<char> = repair.corrected.chars().collect();
+            assert_eq!(checker.check(&corrected), Diagnostic::Valid);
+            match checker.check(line) {
+                Diagnostic::Incomplete { completion } => {
+                    assert!(repair.edits.is_empty());
+                    assert_eq!(repair.completion, completion);
+                }
+                _ => assert!(!repair.edits.is_empty()),
+            }
+        }
+        assert_eq!(repair_edits(&input), 5);
+        assert_eq!(repair_completions(&input), Some(288957));
+
+        let repair = checker.repair(&"([)".chars().collect::<Vec<_>>());
+        assert_eq!(repair.edits.len(), 1);
+        assert_eq!(repair.corrected.len(), 4);
+        let repair = checker.repair(&"(>".chars().collect::<Vec<_>>());
+        assert_eq!(repair.edits.len(), 1);
+        assert_eq!(repair.corrected.len(), 2);
+    }
+
+    // Fewest edits that leave a valid prefix, trying every way to edit every character
+    fn brute_force_edits(checker: &BracketChecker, line: &[char]) -> usize {
+        let brackets: Vec<char> = checker.pairs.iter().flat_map(|&(open, close)| vec![open, close]).collect();
+        let mut best = usize::MAX;
+        let mut choices = vec![0; line.len()];
+        loop {
+            let mut edits = 0;
+            let mut edited = vec![];
+            for (&c, &choice) in line.iter().zip(&choices) {
+                match choice {
+                    0 => edited.push(c),
+                    1 => edits += 1,
+                    n => {
+                        edits += (brackets[n - 2] != c) as usize;
+                        edited.push(brackets[n - 2]);
+                    }
+                }
+            }
+            if checker.scan(&edited).is_ok() {
+                best = best.min(edits);
+            }
+            match choices.iter().position(|&choice| choice < brackets.len() + 1) {
+                Some(i) => {
+                    choices[i] += 1;
+                    for choice in &mut choices[..i] {
+                        *choice = 0;
+                    }
+                }
+                None => return best
+            }
+        }
+    }
+
+    #[test]
+    fn repairs_are_minimal() {
+        let checker = BracketChecker::new(&[('(', ')'), ('[', ']')]);
+        let alphabet = ['(', ')', '[', ']'];
+        for len in 0..=4 {
+            for n in 0..alphabet.len().pow(len) {
+                let line: Vec<char> = (0..len).map(|i| alphabet[n / alphabet.len().pow(i) % alphabet.len()]).collect();
+                let repair = checker.repair(&line);
+                assert_eq!(repair.edits.len(), brute_force_edits(&checker, &line), "{:?}", line);
+                let corrected: Vec<char> = repair.corrected.chars().collect();
+                assert_eq!(checker.check(&corrected), Diagnostic::Valid);
+            }
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use super::cycle::{self, Outcome};
+
 #[aoc_generator(day11)]
 fn generator(input: &str) -> Vec<u32> {
     input
@@ -13,6 +15,8 @@ fn generator(input: &str) -> Vec<u32> {
 
 const WIDTH: usize = 10;
 const HEIGHT: usize = 10;
+// Give up on synchronizing after this many steps
+const MAX_STEPS: usize = 100_000;
 
 fn increment(grid: &mut [u32], i: usize) -> usize {
     // 0 - 9 have not flashed
@@ -33,7 +37,7 @@ fn increment(grid: &mut [u32], i: usize) -> usize {
                 flashes += increment(grid, i - 1 + WIDTH);
             }
         }
-        if x != HEIGHT - 1 {
+        if x != WIDTH - 1 {
             flashes += increment(grid, i + 1);
             if y != 0 {
                 flashes += increment(grid, i + 1 - WIDTH);
@@ -81,14 +85,145 @@ fn simple_loop(input: &[u32]) -> usize {
 }
 
 #[aoc(day11, part2)]
-fn equally_simple_loop(input: &[u32]) -> usize {
+fn equally_simple_loop(input: &[u32]) -> Outcome {
     let mut grid = input.to_owned();
-    let mut step = 0;
-    loop {
-        step += 1;
-        if tick(&mut grid) == WIDTH * HEIGHT {
-            return step 
+    cycle::run(&mut grid, MAX_STEPS, |grid| grid.clone(), |grid| {
+        let synchronized = tick(grid) == WIDTH * HEIGHT;
+        clean(grid);
+        synchronized
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct OctopusGrid {
+    width: usize,
+    height: usize,
+    energy: Vec<u8>
+}
+
+impl OctopusGrid {
+    fn parse(input: &str) -> Self {
+        let lines: Vec<&str> = input.lines().collect();
+        let energy = lines
+            .iter()
+            .flat_map(|l| l.chars().map(|n| n.to_digit(10).unwrap() as u8))
+            .collect();
+        Self { width: lines[0].len(), height: lines.len(), energy }
+    }
+
+    fn len(&self) -> usize {
+        self.energy.len()
+    }
+
+    fn neighbors(&self, i: usize) -> impl Iterator<Item=usize> {
+        let (x, y) = ((i % self.width) as isize, (i / self.width) as isize);
+        let (width, height) = (self.width as isize, self.height as isize);
+        (-1..=1)
+            .flat_map(move |dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+            .filter(move |&(nx, ny)| (nx, ny) != (x, y) && nx >= 0 && ny >= 0 && nx < width && ny < height)
+            .map(move |(nx, ny)| (ny * width + nx) as usize)
+    }
+
+    // Advances one step, and returns the (x, y) of every octopus that flashed, in flash order
+    fn step(&mut self) -> Vec<(usize, usize)> {
+        let mut queue = Vec::new();
+        for (i, energy) in self.energy.iter_mut().enumerate() {
+            *energy += 1;
+            if *energy == 10 {
+                queue.push(i);
+            }
         }
-        clean(&mut grid);
+        // Everything pushed reached exactly 10 once, so nothing flashes twice
+        let mut flashed = Vec::new();
+        while let Some(i) = queue.pop() {
+            flashed.push(i);
+            for n in self.neighbors(i) {
+                if self.energy[n] < 10 {
+                    self.energy[n] += 1;
+                    if self.energy[n] == 10 {
+                        queue.push(n);
+                    }
+                }
+            }
+        }
+        for &i in &flashed {
+            self.energy[i] = 0;
+        }
+        flashed.into_iter().map(|i| (i % self.width, i / self.width)).collect()
+    }
+}
+
+impl std::fmt::Display for OctopusGrid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for row in self.energy.chunks(self.width) {
+            for &n in row {
+                write!(f, "{}", n)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[aoc_generator(day11, part1, Queue)]
+fn octopus_grid(input: &str) -> OctopusGrid {
+    OctopusGrid::parse(input)
+}
+
+#[aoc(day11, part1, Queue)]
+fn queued_flashes(input: &OctopusGrid) -> usize {
+    let mut grid = input.clone();
+    (0..100).map(|_| grid.step().len()).sum()
+}
+
+#[aoc_generator(day11, part2, Queue)]
+fn octopus_grid_again(input: &str) -> OctopusGrid {
+    OctopusGrid::parse(input)
+}
+
+fn synchronization(input: &OctopusGrid, max_steps: usize) -> Outcome {
+    let mut grid = input.clone();
+    cycle::run(&mut grid, max_steps, |grid| grid.energy.clone(), |grid| grid.step().len() == grid.len())
+}
+
+#[aoc(day11, part2, Queue)]
+fn queued_synchronization(input: &OctopusGrid) -> Outcome {
+    synchronization(input, MAX_STEPS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+5483143223
+2745854711
+5264556173
+6141336146
+6357385478
+4167524645
+2176841721
+6882881134
+4846848554
+5283751526";
+
+    #[test]
+    fn example_flashes_and_synchronizes() {
+        assert_eq!(simple_loop(&generator(EXAMPLE)), 1656);
+        assert_eq!(equally_simple_loop(&generator(EXAMPLE)), Outcome::Stable(195));
+        let grid = OctopusGrid::parse(EXAMPLE);
+        assert_eq!(queued_flashes(&grid), 1656);
+        assert_eq!(queued_synchronization(&grid), Outcome::Stable(195));
+        assert_eq!(synchronization(&grid, 100), Outcome::Limit(100));
+    }
+
+    #[test]
+    fn any_size_grid() {
+        // Synchronizes immediately, then every 10 steps
+        let grid = OctopusGrid::parse("999\n999");
+        assert_eq!(synchronization(&grid, 5), Outcome::Stable(1));
+        let mut grid = OctopusGrid::parse("11111\n19991\n19191\n19991\n11111");
+        assert_eq!(grid.step().len(), 9);
+        assert_eq!(grid.to_string(), "34543\n40004\n50005\n40004\n34543\n");
     }
 }
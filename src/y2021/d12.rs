@@ -1,4 +1,4 @@
-use std::collections::{HashMap, BTreeMap, BTreeSet};
+use std::collections::{HashMap, HashSet};
 
 #[aoc_generator(day12)]
 fn generator(input: &str) -> Vec<(String, String)> {
@@ -11,94 +11,482 @@ fn generator(input: &str) -> Vec<(String, String)> {
         .collect()
 }
 
-fn recursively_count(paths: &BTreeMap<usize, Vec<usize>>, big: &BTreeSet<usize>, visited: &Vec<usize>, current: usize, goal: usize) -> usize {
-    let options = &paths[&current];
-    let mut sum = 0;
-    let mut new = visited.clone();
-    if !big.contains(&current) {
-        new.push(current);
+struct CaveGraph {
+    names: Vec<String>,
+    edges: Vec<Vec<usize>>,
+    // Each small cave gets its own bit in the visited mask, big caves get None
+    small_bits: Vec<Option<u32>>,
+    start: usize,
+    end: usize
+}
+
+impl CaveGraph {
+    fn new(input: &[(String, String)]) -> Self {
+        let mut ids = HashMap::new();
+        let mut names = vec![];
+        let mut edges: Vec<Vec<usize>> = vec![];
+        let mut id = |name: &String, names: &mut Vec<String>, edges: &mut Vec<Vec<usize>>| {
+            *ids.entry(name.clone()).or_insert_with(|| {
+                names.push(name.clone());
+                edges.push(vec![]);
+                names.len() - 1
+            })
+        };
+        for (f, t) in input {
+            let f = id(f, &mut names, &mut edges);
+            let t = id(t, &mut names, &mut edges);
+            edges[f].push(t);
+            edges[t].push(f);
+        }
+        let mut small_count = 0;
+        let small_bits = names
+            .iter()
+            .map(|name| (&name.to_ascii_uppercase() != name).then(|| {
+                small_count += 1;
+                small_count - 1
+            }))
+            .collect();
+        assert!(small_count <= 64, "too many small caves for the visited mask");
+        let start = names.iter().position(|name| name == "start").unwrap();
+        let end = names.iter().position(|name| name == "end").unwrap();
+        CaveGraph { names, edges, small_bits, start, end }
     }
-    for &option in options {
-        if option == goal {
-            sum += 1;
+
+    // Paths from start to end, visiting small caves at most once, except for a single small
+    // cave that may be visited twice if `revisit` is set. The number of paths from a cave only
+    // depends on which small caves are used up and whether the revisit is, so that gets cached.
+    fn count_paths(&self, revisit: bool) -> usize {
+        let mut cache = HashMap::new();
+        let visited = 1 << self.small_bits[self.start].unwrap();
+        self.count_from(self.start, visited, !revisit, &mut cache)
+    }
+
+    fn count_from(&self, current: usize, visited: u64, revisit_used: bool, cache: &mut HashMap<(usize, u64, bool), usize>) -> usize {
+        if let Some(&count) = cache.get(&(current, visited, revisit_used)) {
+            return count
         }
-        else if !visited.contains(&option) {
-            sum += recursively_count(paths, big, &new, option, goal);
+        let mut sum = 0;
+        for &option in &self.edges[current] {
+            if option == self.start {
+                continue;
+            }
+            if option == self.end {
+                sum += 1;
+                continue;
+            }
+            match self.small_bits[option] {
+                None => sum += self.count_from(option, visited, revisit_used, cache),
+                Some(bit) if visited & 1 << bit == 0 => {
+                    sum += self.count_from(option, visited | 1 << bit, revisit_used, cache)
+                },
+                Some(_) if !revisit_used => sum += self.count_from(option, visited, true, cache),
+                Some(_) => ()
+            }
         }
+        cache.insert((current, visited, revisit_used), sum);
+        sum
     }
-    sum
 }
 
-fn recursively_count2(paths: &BTreeMap<usize, Vec<usize>>, big: &BTreeSet<usize>, visited: &Vec<usize>, current: usize, start: usize, goal: usize, extra_used: bool) -> usize {
-    let options = &paths[&current];
-    let mut sum = 0;
-    let mut new = visited.clone();
-    if !big.contains(&current) {
-        new.push(current);
+// Which paths count. By default small caves can be visited once and big caves any number
+// of times, which is part 1. Part 2 is the same with a revisit budget of 1.
+#[derive(Debug, Clone, Default)]
+struct VisitPolicy {
+    limits: HashMap<String, usize>,
+    revisits: usize,
+    forbidden: HashSet<String>,
+    waypoints: Vec<String>
+}
+
+impl VisitPolicy {
+    // Overrides how many times a cave may be visited
+    fn limit(mut self, cave: &str, visits: usize) -> Self {
+        self.limits.insert(cave.to_string(), visits);
+        self
     }
-    for &option in options {
-        if option != start {
-            if option == goal {
-                sum += 1;
+
+    // Extra visits, past any cave's limit, that a path may spend in total
+    fn revisits(mut self, budget: usize) -> Self {
+        self.revisits = budget;
+        self
+    }
+
+    fn forbid(mut self, cave: &str) -> Self {
+        self.forbidden.insert(cave.to_string());
+        self
+    }
+
+    // A cave that every path has to pass through
+    fn require(mut self, cave: &str) -> Self {
+        if !self.waypoints.iter().any(|waypoint| waypoint == cave) {
+            self.waypoints.push(cave.to_string());
+        }
+        self
+    }
+}
+
+// A policy in terms of cave ids
+struct Rules {
+    limits: Vec<Option<usize>>,
+    forbidden: Vec<bool>,
+    // Which of the waypoints each cave is
+    waypoint_ids: Vec<Option<usize>>,
+    waypoints: usize,
+    // A waypoint that isn't in the graph can never be reached, so nothing will match
+    unreachable: bool
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Walk {
+    visits: Vec<usize>,
+    budget: usize,
+    reached: Vec<bool>
+}
+
+impl Rules {
+    fn satisfied(&self, walk: &Walk) -> bool {
+        !self.unreachable && walk.reached.iter().all(|&reached| reached)
+    }
+}
+
+impl CaveGraph {
+    fn rules(&self, policy: &VisitPolicy) -> Rules {
+        let limits = self.names
+            .iter()
+            .enumerate()
+            .map(|(id, name)| match policy.limits.get(name) {
+                Some(&visits) => Some(visits),
+                None => self.small_bits[id].map(|_| 1)
+            })
+            .collect();
+        let forbidden = self.names.iter().map(|name| policy.forbidden.contains(name)).collect();
+        let mut waypoint_ids = vec![None; self.names.len()];
+        let mut waypoints = 0;
+        let mut unreachable = false;
+        for name in &policy.waypoints {
+            match self.names.iter().position(|n| n == name) {
+                Some(id) if waypoint_ids[id].is_none() => {
+                    waypoint_ids[id] = Some(waypoints);
+                    waypoints += 1;
+                }
+                Some(_) => (),
+                None => unreachable = true
+            }
+        }
+        Rules { limits, forbidden, waypoint_ids, waypoints, unreachable }
+    }
+
+    // The walk after entering a cave, if the rules allow it
+    fn enter(&self, rules: &Rules, walk: &Walk, cave: usize) -> Option<Walk> {
+        if cave == self.start || rules.forbidden[cave] {
+            return None
+        }
+        let mut next = walk.clone();
+        if let Some(limit) = rules.limits[cave] {
+            if next.visits[cave] >= limit {
+                next.budget = next.budget.checked_sub(1)?;
             }
-            else if !visited.contains(&option) {
-                sum += recursively_count2(paths, big, &new, option, start, goal, extra_used);
+            next.visits[cave] += 1;
+        }
+        if let Some(waypoint) = rules.waypoint_ids[cave] {
+            next.reached[waypoint] = true;
+        }
+        Some(next)
+    }
+
+    fn first_walk(&self, rules: &Rules, policy: &VisitPolicy) -> Walk {
+        let mut visits = vec![0; self.names.len()];
+        visits[self.start] = 1;
+        let mut reached = vec![false; rules.waypoints];
+        if let Some(waypoint) = rules.waypoint_ids[self.start] {
+            reached[waypoint] = true;
+        }
+        Walk { visits, budget: policy.revisits, reached }
+    }
+
+    // Same idea as `count_from`, with the visit counts standing in for the mask
+    fn count_with(&self, policy: &VisitPolicy) -> usize {
+        let rules = self.rules(policy);
+        let walk = self.first_walk(&rules, policy);
+        self.count_walk(&rules, self.start, walk, &mut HashMap::new())
+    }
+
+    fn count_walk(&self, rules: &Rules, current: usize, walk: Walk, cache: &mut HashMap<(usize, Walk), usize>) -> usize {
+        if current == self.end {
+            return rules.satisfied(&walk) as usize
+        }
+        if let Some(&count) = cache.get(&(current, walk.clone())) {
+            return count
+        }
+        let sum = self.edges[current]
+            .iter()
+            .filter_map(|&option| Some((option, self.enter(rules, &walk, option)?)))
+            .map(|(option, next)| self.count_walk(rules, option, next, cache))
+            .sum();
+        cache.insert((current, walk), sum);
+        sum
+    }
+
+    // Every path the policy allows, as cave names from start to end
+    fn paths_with(&self, policy: &VisitPolicy) -> Vec<Vec<&str>> {
+        let rules = self.rules(policy);
+        let mut paths = vec![];
+        let mut stack = vec![(vec![self.start], self.first_walk(&rules, policy))];
+        while let Some((path, walk)) = stack.pop() {
+            let current = path[path.len() - 1];
+            if current == self.end {
+                if rules.satisfied(&walk) {
+                    paths.push(path.iter().map(|&id| self.names[id].as_str()).collect());
+                }
+                continue;
             }
-            else if !extra_used  {
-                sum += recursively_count2(paths, big, &new, option, start, goal, true);
+            for &option in self.edges[current].iter().rev() {
+                if let Some(next) = self.enter(&rules, &walk, option) {
+                    let mut path = path.clone();
+                    path.push(option);
+                    stack.push((path, next));
+                }
             }
         }
+        paths
     }
-    sum
 }
 
-#[aoc(day12, part1)]
-fn count_paths(input: &[(String, String)]) -> usize {
-    // I had a lot of code for graph substitutions but I deleted it all, it was too buggy :(
-    let mut names = HashMap::new();
-    let mut paths = BTreeMap::new();
-    let mut big = BTreeSet::new();
-    for (f, t) in input {
-        let l = names.len();
-        names.entry(f.clone()).or_insert(l);
-        let l = names.len();
-        names.entry(t.clone()).or_insert(l);
-        paths.entry(names[f]).or_insert(vec![]).push(names[t]);
-        paths.entry(names[t]).or_insert(vec![]).push(names[f]);
-        if &f.to_ascii_uppercase() == f {
-            big.insert(names[f]);
+enum Highlight<'a> {
+    // A path as cave names, such as one from `paths_with`
+    Path(&'a [&'a str]),
+    // How many times the paths allowed by the policy go through each tunnel
+    Usage(&'a VisitPolicy),
+}
+
+impl CaveGraph {
+    fn tunnels(&self) -> Vec<(usize, usize)> {
+        let mut tunnels: Vec<_> = self.edges
+            .iter()
+            .enumerate()
+            .flat_map(|(f, ts)| ts.iter().filter(move |&&t| f < t).map(move |&t| (f, t)))
+            .collect();
+        tunnels.sort_unstable();
+        tunnels.dedup();
+        tunnels
+    }
+
+    // Graphviz source: big caves are boxes, small caves are ellipses, start and end are doubled
+    fn to_dot(&self, highlight: Option<Highlight>) -> String {
+        let mut used: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut on_path = vec![false; self.names.len()];
+        let add_path = |path: &[&str], used: &mut HashMap<_, _>| {
+            let ids: Vec<usize> = path
+                .iter()
+                .filter_map(|&name| self.names.iter().position(|n| n == name))
+                .collect();
+            for pair in ids.windows(2) {
+                *used.entry((pair[0].min(pair[1]), pair[0].max(pair[1]))).or_insert(0) += 1;
+            }
+            ids
+        };
+        match highlight {
+            None => (),
+            Some(Highlight::Path(path)) => {
+                for id in add_path(path, &mut used) {
+                    on_path[id] = true;
+                }
+            },
+            Some(Highlight::Usage(policy)) => {
+                for path in self.paths_with(policy) {
+                    add_path(&path, &mut used);
+                }
+            },
+        }
+        let most = used.values().copied().max().unwrap_or(1);
+
+        let mut dot = String::from("graph caves {\n");
+        for (id, name) in self.names.iter().enumerate() {
+            let shape = match (id == self.start || id == self.end, self.small_bits[id].is_some()) {
+                (true, _) => "doublecircle",
+                (false, true) => "ellipse",
+                (false, false) => "box",
+            };
+            let color = if on_path[id] { ", color=red" } else { "" };
+            dot += &format!("    \"{}\" [shape={}{}];\n", name, shape, color);
         }
-        if &t.to_ascii_uppercase() == t {
-            big.insert(names[t]);
+        for (f, t) in self.tunnels() {
+            let style = match (&highlight, used.get(&(f, t))) {
+                (Some(Highlight::Path(_)), Some(_)) => " [color=red, penwidth=3]".to_string(),
+                (Some(Highlight::Usage(_)), Some(&n)) => format!(" [label={}, penwidth={:.1}]", n, 1.0 + 4.0 * n as f64 / most as f64),
+                (Some(Highlight::Usage(_)), None) => " [style=dashed]".to_string(),
+                _ => String::new(),
+            };
+            dot += &format!("    \"{}\" -- \"{}\"{};\n", self.names[f], self.names[t], style);
         }
+        dot += "}\n";
+        dot
     }
-    let start_node = names["start"];
-    let end_node = names["end"];
-    recursively_count(&paths, &big, &vec![start_node], start_node, end_node)
+}
+
+#[aoc(day12, part1)]
+fn count_paths(input: &[(String, String)]) -> usize {
+    CaveGraph::new(input).count_paths(false)
 }
 
 #[aoc(day12, part2)]
 fn count_paths_repetition(input: &[(String, String)]) -> usize {
-    // I had a lot of code for graph substitutions but I deleted it all, it was too buggy :(
-    let mut names = HashMap::new();
-    let mut paths = BTreeMap::new();
-    let mut big = BTreeSet::new();
-    for (f, t) in input {
-        let l = names.len();
-        names.entry(f.clone()).or_insert(l);
-        let l = names.len();
-        names.entry(t.clone()).or_insert(l);
-        paths.entry(names[f]).or_insert(vec![]).push(names[t]);
-        paths.entry(names[t]).or_insert(vec![]).push(names[f]);
-        if &f.to_ascii_uppercase() == f {
-            big.insert(names[f]);
+    CaveGraph::new(input).count_paths(true)
+}
+
+#[aoc(day12, part1, Policy)]
+fn count_paths_policy(input: &[(String, String)]) -> usize {
+    CaveGraph::new(input).count_with(&VisitPolicy::default())
+}
+
+#[aoc(day12, part2, Policy)]
+fn count_paths_repetition_policy(input: &[(String, String)]) -> usize {
+    CaveGraph::new(input).count_with(&VisitPolicy::default().revisits(1))
+}
+
+// The part 2 paths, but enumerated one by one
+#[aoc(day12, part2, Enumerate)]
+fn enumerate_paths_repetition(input: &[(String, String)]) -> usize {
+    CaveGraph::new(input).paths_with(&VisitPolicy::default().revisits(1)).len()
+}
+
+// Part 1 split into the paths that avoid some small cave and the ones that pass through it
+#[aoc(day12, part1, Decomposed)]
+fn count_paths_decomposed(input: &[(String, String)]) -> usize {
+    let graph = CaveGraph::new(input);
+    let policy = VisitPolicy::default();
+    let cave = graph.names
+        .iter()
+        .enumerate()
+        .find(|&(id, _)| graph.small_bits[id].is_some() && id != graph.start && id != graph.end)
+        .map(|(_, name)| name.as_str())
+        .unwrap();
+    graph.count_with(&policy.clone().forbid(cave)) + graph.count_with(&policy.require(cave))
+}
+
+// Part 2 as part 1, plus the extra paths from letting each small cave in turn be visited twice
+#[aoc(day12, part2, Decomposed)]
+fn count_paths_repetition_decomposed(input: &[(String, String)]) -> usize {
+    let graph = CaveGraph::new(input);
+    let once = graph.count_with(&VisitPolicy::default());
+    once + graph.names
+        .iter()
+        .enumerate()
+        .filter(|&(id, _)| graph.small_bits[id].is_some() && id != graph.start && id != graph.end)
+        .map(|(_, name)| graph.count_with(&VisitPolicy::default().limit(name, 2)) - once)
+        .sum::<usize>()
+}
+
+// The first part 1 path, drawn over the cave system, if there is one
+#[aoc(day12, part1, Dot)]
+fn dot_first_path(input: &[(String, String)]) -> Option<String> {
+    let graph = CaveGraph::new(input);
+    let paths = graph.paths_with(&VisitPolicy::default());
+    paths.first().map(|path| graph.to_dot(Some(Highlight::Path(path))))
+}
+
+#[aoc(day12, part2, Dot)]
+fn dot_usage(input: &[(String, String)]) -> String {
+    let graph = CaveGraph::new(input);
+    graph.to_dot(Some(Highlight::Usage(&VisitPolicy::default().revisits(1))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SMALL: &str = "start-A\nstart-b\nA-c\nA-b\nb-d\nA-end\nb-end";
+    const MEDIUM: &str = "dc-end\nHN-start\nstart-kj\ndc-start\ndc-HN\nLN-dc\nHN-end\nkj-sa\nkj-HN\nkj-dc";
+    const LARGE: &str = "fs-end\nhe-DX\nfs-he\nstart-DX\npj-DX\nend-zg\nzg-sl\nzg-pj\npj-he\nRW-he\nfs-DX\npj-RW\nzg-RW\nstart-pj\nhe-WI\nzg-he\npj-fs\nstart-RW";
+
+    #[test]
+    fn examples() {
+        for &(input, once, twice) in &[(SMALL, 10, 36), (MEDIUM, 19, 103), (LARGE, 226, 3509)] {
+            let input = generator(input);
+            assert_eq!(count_paths(&input), once);
+            assert_eq!(count_paths_repetition(&input), twice);
+            assert_eq!(count_paths_policy(&input), once);
+            assert_eq!(count_paths_repetition_policy(&input), twice);
+            assert_eq!(enumerate_paths_repetition(&input), twice);
+            assert_eq!(count_paths_decomposed(&input), once);
+            assert_eq!(count_paths_repetition_decomposed(&input), twice);
         }
-        if &t.to_ascii_uppercase() == t {
-            big.insert(names[t]);
+    }
+
+    #[test]
+    fn forbidden_and_required_caves() {
+        let graph = CaveGraph::new(&generator(LARGE));
+        let policy = VisitPolicy::default().revisits(1).forbid("sl").require("fs").require("WI");
+        let paths = graph.paths_with(&policy);
+        assert!(!paths.is_empty());
+        assert_eq!(paths.len(), graph.count_with(&policy));
+        for path in &paths {
+            assert!(path.contains(&"fs") && path.contains(&"WI") && !path.contains(&"sl"));
         }
+        assert_eq!(graph.count_with(&VisitPolicy::default().require("nowhere")), 0);
     }
-    let start_node = names["start"];
-    let end_node = names["end"];
-    recursively_count2(&paths, &big, &vec![], start_node, start_node, end_node, false)
-}
 
+    #[test]
+    fn requiring_a_cave_twice_is_requiring_it_once() {
+        let graph = CaveGraph::new(&generator(LARGE));
+        let once = VisitPolicy::default().revisits(1).require("zg");
+        assert_eq!(graph.count_with(&once.clone().require("zg")), graph.count_with(&once));
+    }
+
+    #[test]
+    fn large_limits_and_many_waypoints() {
+        // start-A-end, with b hanging off A, visited up to 300 (or 1 + 300) times
+        let graph = CaveGraph::new(&generator("start-A\nA-b\nA-end"));
+        assert_eq!(graph.count_with(&VisitPolicy::default().limit("b", 300)), 301);
+        assert_eq!(graph.count_with(&VisitPolicy::default().revisits(300)), 302);
+
+        // A single path through 70 waypoints, alternating big and small caves
+        let names: Vec<String> = (0..35).flat_map(|n| vec![format!("C{}", n), format!("c{}", n)]).collect();
+        let mut caves = vec!["start".to_string()];
+        caves.extend(names.iter().cloned());
+        caves.push("end".to_string());
+        let edges: Vec<(String, String)> = caves.windows(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect();
+        let policy = names.iter().fold(VisitPolicy::default(), |policy, name| policy.require(name));
+        assert_eq!(CaveGraph::new(&edges).count_with(&policy), 1);
+    }
+
+    #[test]
+    fn dot_output() {
+        let graph = CaveGraph::new(&generator(SMALL));
+        let has = |dot: &str, line: &str| dot.lines().any(|l| l.trim() == line);
+
+        let plain = graph.to_dot(None);
+        assert!(plain.starts_with("graph caves {\n") && plain.ends_with("}\n"));
+        assert!(has(&plain, r#""start" [shape=doublecircle];"#));
+        assert!(has(&plain, r#""end" [shape=doublecircle];"#));
+        assert!(has(&plain, r#""A" [shape=box];"#));
+        assert!(has(&plain, r#""b" [shape=ellipse];"#));
+        assert!(has(&plain, r#""b" -- "d";"#));
+        assert_eq!(plain.matches(" -- ").count(), 7);
+
+        let path = graph.to_dot(Some(Highlight::Path(&["start", "b", "end"])));
+        assert!(has(&path, r#""start" [shape=doublecircle, color=red];"#));
+        assert!(has(&path, r#""b" [shape=ellipse, color=red];"#));
+        assert!(has(&path, r#""A" [shape=box];"#));
+        assert!(has(&path, r#""start" -- "b" [color=red, penwidth=3];"#));
+        assert!(has(&path, r#""b" -- "end" [color=red, penwidth=3];"#));
+        assert!(has(&path, r#""start" -- "A";"#));
+        assert_eq!(path.matches("penwidth").count(), 2);
+
+        // Nothing goes down to d and back when b can only be visited once
+        let usage = graph.to_dot(Some(Highlight::Usage(&VisitPolicy::default())));
+        assert!(has(&usage, r#""b" -- "d" [style=dashed];"#));
+        assert!(has(&usage, r#""start" -- "b" [label=3, penwidth=2.2];"#));
+        assert!(has(&usage, r#""A" -- "c" [label=10, penwidth=5.0];"#));
+        let usage = dot_usage(&generator(SMALL));
+        assert!(has(&usage, r#""b" -- "d" [label=16, penwidth=2.0];"#));
+        assert!(!usage.contains("dashed"));
+    }
+
+    #[test]
+    fn dot_first_path_needs_a_path() {
+        let dot = dot_first_path(&generator(SMALL)).unwrap();
+        assert!(dot.lines().any(|l| l.trim() == r#""start" -- "A" [color=red, penwidth=3];"#));
+        assert_eq!(dot_first_path(&generator("start-a\nb-end")), None);
+    }
+}
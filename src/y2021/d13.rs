@@ -1,5 +1,9 @@
 #![allow(dead_code)]
 
+use std::collections::HashSet;
+
+use super::ocr::{self, OcrError};
+
 #[aoc_generator(day13)]
 fn generator(input: &str) -> (Vec<(usize, usize)>, Vec<Line>) {
     let mut iter = input.split("\n\n");
@@ -93,6 +97,16 @@ impl Grid {
         }
     }
 
+    fn read_text(&mut self) -> Result<String, OcrError> {
+        self.set_orientation(true);
+        ocr::recognize(&self.bits, self.width)
+    }
+
+    fn render(&mut self, format: Format) -> String {
+        self.set_orientation(true);
+        render(&self.bits, self.width, format)
+    }
+
     fn show(&mut self) {
         self.set_orientation(true);
         for chunk in self.bits.chunks(self.width) {
@@ -124,13 +138,8 @@ fn one_fold((points, folds): &(Vec<(usize, usize)>, Vec<Line>)) -> usize {
     grid.count_ones()
 }
 
-struct No;
-impl std::fmt::Display for No {
-    fn fmt(&self, _f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { Ok(()) }
-}
-
 #[aoc(day13, part2)]
-fn activation_code((points, folds): &(Vec<(usize, usize)>, Vec<Line>)) -> No {
+fn activation_code((points, folds): &(Vec<(usize, usize)>, Vec<Line>)) -> Result<String, OcrError> {
     let mut grid = Grid::from_slice(&points);
     for fold in folds {
         match fold {
@@ -142,7 +151,314 @@ fn activation_code((points, folds): &(Vec<(usize, usize)>, Vec<Line>)) -> No {
             },
         }
     }
-    grid.show();
-    No
+    grid.read_text()
+}
+
+// Inclusive on both ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BoundingBox {
+    min: (i64, i64),
+    max: (i64, i64),
+}
+
+// Only keeps the dots, so the size of the paper doesn't matter, and a fold can be anywhere.
+// When more than half the paper is folded over, the flap ends up at negative coordinates.
+#[derive(Debug, Clone)]
+struct Paper {
+    dots: HashSet<(i64, i64)>,
+}
+
+impl Paper {
+    fn from_slice(slice: &[(usize, usize)]) -> Self {
+        Paper { dots: slice.iter().map(|&(x, y)| (x as i64, y as i64)).collect() }
+    }
+
+    // Dots on the fold line itself disappear into the crease
+    fn fold(&mut self, line: Line) -> Option<BoundingBox> {
+        self.dots = self.dots
+            .iter()
+            .filter_map(|&(x, y)| match line {
+                Line::Horizontal(f) => {
+                    let f = f as i64;
+                    (y != f).then(|| (x, if y > f { 2 * f - y } else { y }))
+                },
+                Line::Vertical(f) => {
+                    let f = f as i64;
+                    (x != f).then(|| (if x > f { 2 * f - x } else { x }, y))
+                },
+            })
+            .collect();
+        self.bounding_box()
+    }
+
+    // The bounding box after every fold
+    fn fold_all(&mut self, folds: &[Line]) -> Vec<Option<BoundingBox>> {
+        folds.iter().map(|&line| self.fold(line)).collect()
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let xs = self.dots.iter().map(|&(x, _)| x);
+        let ys = self.dots.iter().map(|&(_, y)| y);
+        Some(BoundingBox {
+            min: (xs.clone().min()?, ys.clone().min()?),
+            max: (xs.max()?, ys.max()?),
+        })
+    }
+
+    // A dense row-major bitmap from the origin (or from any dots folded past it) to the
+    // bottom right dot, and its width. Cropping to the dots instead would shift letters
+    // that start with a blank column, like I.
+    fn to_bits(&self) -> (Vec<bool>, usize) {
+        match self.bounding_box() {
+            Some(BoundingBox { min, max }) => {
+                let origin = (min.0.min(0), min.1.min(0));
+                let width = (max.0 - origin.0 + 1) as usize;
+                let height = (max.1 - origin.1 + 1) as usize;
+                let mut bits = vec![false; width * height];
+                for &(x, y) in &self.dots {
+                    bits[(y - origin.1) as usize * width + (x - origin.0) as usize] = true;
+                }
+                (bits, width)
+            },
+            None => (vec![], 1)
+        }
+    }
+
+    fn to_text(&self) -> Result<String, OcrError> {
+        let (bits, width) = self.to_bits();
+        ocr::recognize(&bits, width)
+    }
+
+    fn render(&self, format: Format) -> String {
+        let (bits, width) = self.to_bits();
+        render(&bits, width, format)
+    }
+
+    // The paper before folding and after every fold
+    fn frames(&self, folds: &[Line], format: Format) -> Vec<String> {
+        let mut paper = self.clone();
+        let mut frames = vec![paper.render(format)];
+        for &line in folds {
+            paper.fold(line);
+            frames.push(paper.render(format));
+        }
+        frames
+    }
 }
 
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    // Plain (ASCII) portable bitmap, where 1 is black. A plain file holds a single image.
+    Pbm,
+    // A black square of this many units per dot
+    Svg { scale: usize },
+}
+
+fn render(bits: &[bool], width: usize, format: Format) -> String {
+    let height = bits.len() / width;
+    match format {
+        Format::Pbm => {
+            let mut out = format!("P1\n{} {}\n", width, height);
+            // Pixels don't need separating, but lines can't be longer than 70 characters
+            for row in bits.chunks(width) {
+                for line in row.chunks(70) {
+                    out.extend(line.iter().map(|&b| if b { '1' } else { '0' }));
+                    out += "\n";
+                }
+            }
+            out
+        },
+        Format::Svg { scale } => {
+            let mut out = format!(
+                "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\n",
+                width * scale,
+                height * scale
+            );
+            out += "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n";
+            for (i, _) in bits.iter().enumerate().filter(|&(_, &b)| b) {
+                out += &format!(
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"/>\n",
+                    i % width * scale,
+                    i / width * scale,
+                    scale,
+                    scale
+                );
+            }
+            out += "</svg>\n";
+            out
+        },
+    }
+}
+
+#[aoc(day13, part1, Sparse)]
+fn one_sparse_fold((points, folds): &(Vec<(usize, usize)>, Vec<Line>)) -> usize {
+    let mut paper = Paper::from_slice(points);
+    paper.fold(folds[0]);
+    paper.dots.len()
+}
+
+#[aoc(day13, part2, Sparse)]
+fn sparse_activation_code((points, folds): &(Vec<(usize, usize)>, Vec<Line>)) -> Result<String, OcrError> {
+    let mut paper = Paper::from_slice(points);
+    paper.fold_all(folds);
+    paper.to_text()
+}
+
+#[aoc(day13, part2, Svg)]
+fn activation_code_svg((points, folds): &(Vec<(usize, usize)>, Vec<Line>)) -> String {
+    let mut paper = Paper::from_slice(points);
+    paper.fold_all(folds);
+    paper.render(Format::Svg { scale: 10 })
+}
+
+// One complete image per frame, since plain PBM files can't hold more than one
+struct Frames(Vec<String>);
+
+// Frames never contain blank lines, so a blank line between them is enough to split them up again
+impl std::fmt::Display for Frames {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.join("\n"))
+    }
+}
+
+#[aoc(day13, part2, Pbm)]
+fn fold_frames_pbm((points, folds): &(Vec<(usize, usize)>, Vec<Line>)) -> Frames {
+    Frames(Paper::from_slice(points).frames(folds, Format::Pbm))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+6,10
+0,14
+9,10
+0,3
+10,4
+4,11
+6,0
+6,12
+4,1
+0,13
+10,12
+3,4
+3,0
+8,4
+1,10
+2,14
+8,10
+9,0
+
+fold along y=7
+fold along x=5";
+
+    #[test]
+    fn example_folds() {
+        let (points, folds) = generator(EXAMPLE);
+        assert_eq!(one_sparse_fold(&(points.clone(), folds.clone())), 17);
+        let mut paper = Paper::from_slice(&points);
+        let boxes = paper.fold_all(&folds);
+        assert_eq!(boxes.last(), Some(&Some(BoundingBox { min: (0, 0), max: (4, 4) })));
+        assert_eq!(paper.dots.len(), 16);
+        assert_eq!(paper.render(Format::Pbm).lines().nth(1), Some("5 5"));
+    }
+
+    // Dots drawing the text in the small font, moved right and down by `offset`
+    fn letters(text: &[&str], offset: (usize, usize)) -> Vec<(usize, usize)> {
+        text.iter()
+            .enumerate()
+            .flat_map(|(y, row)| row
+                .chars()
+                .enumerate()
+                .filter(|&(_, c)| c == '#')
+                .map(move |(x, _)| (x + offset.0, y + offset.1)))
+            .collect()
+    }
+
+    const IA: [&str; 6] = [
+        ".###..##.",
+        "..#..#..#",
+        "..#..#..#",
+        "..#..####",
+        "..#..#..#",
+        ".###.#..#",
+    ];
+
+    const AI: [&str; 6] = [
+        ".##...###",
+        "#..#...#.",
+        "#..#...#.",
+        "####...#.",
+        "#..#...#.",
+        "#..#..###",
+    ];
+
+    #[test]
+    fn text_starting_with_a_blank_column() {
+        let points = letters(&IA, (0, 0));
+        assert_eq!(Paper::from_slice(&points).to_text(), Ok("IA".to_string()));
+        assert_eq!(Grid::from_slice(&points).read_text(), Ok("IA".to_string()));
+    }
+
+    #[test]
+    fn text_folded_past_the_origin() {
+        // Folding at x = 3 mirrors everything right of it past the left edge
+        let points: Vec<_> = letters(&AI, (0, 0)).into_iter().map(|(x, y)| (13 - x, y)).collect();
+        let mut paper = Paper::from_slice(&points);
+        let bounding_box = paper.fold(Line::Vertical(3));
+        assert_eq!(bounding_box.map(|b| b.min.0), Some(-7));
+        assert_eq!(paper.to_text(), Ok("AI".to_string()));
+    }
+
+    #[test]
+    fn frames_per_fold() {
+        let (points, folds) = generator(EXAMPLE);
+        let frames = Paper::from_slice(&points).frames(&folds, Format::Pbm);
+        assert_eq!(frames.len(), folds.len() + 1);
+        let headers: Vec<_> = frames.iter().map(|frame| frame.lines().nth(1).unwrap()).collect();
+        assert_eq!(headers, vec!["11 15", "11 5", "5 5"]);
+        let dots: Vec<_> = frames.iter().map(|frame| frame.lines().skip(2).collect::<String>().matches('1').count()).collect();
+        assert_eq!(dots, vec![18, 17, 16]);
+        assert_eq!(frames[2], "P1\n5 5\n11111\n10001\n10001\n10001\n11111\n");
+        assert!(frames.iter().all(|frame| frame.matches("P1").count() == 1));
+
+        let shown = fold_frames_pbm(&(points, folds)).to_string();
+        assert_eq!(shown, frames.join("\n"));
+        assert_eq!(shown.split("\n\n").count(), frames.len());
+    }
+
+    #[test]
+    fn pbm_lines_are_short() {
+        let paper = Paper::from_slice(&[(0, 0), (74, 1)]);
+        let pbm = paper.render(Format::Pbm);
+        assert!(pbm.lines().all(|line| line.len() <= 70));
+        let pixels: String = pbm.lines().skip(2).collect();
+        assert_eq!(pixels.len(), 150);
+        assert_eq!(pixels.match_indices('1').map(|(i, _)| i).collect::<Vec<_>>(), vec![0, 149]);
+    }
+
+    #[test]
+    fn svg_has_a_square_per_dot() {
+        let (points, folds) = generator(EXAMPLE);
+        let svg = activation_code_svg(&(points, folds));
+        assert!(svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" width="50" height="50">"#));
+        assert!(svg.ends_with("</svg>\n"));
+        assert_eq!(svg.matches("<rect x=").count(), 16);
+        assert!(svg.contains(r#"<rect x="40" y="20" width="10" height="10"/>"#));
+        assert!(!svg.contains(r#"<rect x="10" y="10""#));
+    }
+
+    #[test]
+    fn grids_render_like_paper() {
+        let points = letters(&IA, (0, 0));
+        let paper = Paper::from_slice(&points);
+        let mut grid = Grid::from_slice(&points);
+        for format in [Format::Pbm, Format::Svg { scale: 3 }] {
+            assert_eq!(grid.render(format), paper.render(format));
+        }
+        let svg = grid.render(Format::Svg { scale: 3 });
+        assert!(svg.contains(r#"width="27" height="18""#));
+        assert_eq!(svg.matches("<rect x=").count(), points.len());
+    }
+}
@@ -1,25 +1,20 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use itertools::Itertools;
+use nalgebra::{ClosedAdd, ClosedMul, DMatrix, DVector, Scalar};
+use num_bigint::BigUint;
+use num_traits::{One, Zero};
 
+#[derive(Debug)]
 struct Polymers {
     template: Vec<usize>,
     rules: Vec<usize>,
+    // Pairs without a rule still get element 0 in `rules`, but don't grow when expanded
+    has_rule: Vec<bool>,
     translation: Vec<u8>,
     count: usize,
 }
 
-impl Polymers {
-    #[allow(dead_code)]
-    fn display_pairs(&self, pairs: &[usize]) {
-        for (i, &pair) in pairs.iter().enumerate() {
-            let (a, b) = unkey(i, self.count);
-            println!("{}{}: {}", self.translation[a] as char, self.translation[b] as char, pair);
-        }
-        println!();
-    }
-}
-
 fn add_entry(map: &mut BTreeMap<u8, usize>, key: u8) -> usize {
     let default = map.len();
     *map.entry(key).or_insert(default)
@@ -56,9 +51,11 @@ fn polymers(input: &str) -> Polymers {
     let count = translation.len();
 
     let mut rules = vec![0usize; count * count];
+    let mut has_rule = vec![false; count * count];
 
     for (a, b, c) in rule_indices {
         rules[key(a, b, count)] = c;
+        has_rule[key(a, b, count)] = true;
     }
 
     let template = template_indices.iter().map(|n| translation[n]).collect();
@@ -68,6 +65,7 @@ fn polymers(input: &str) -> Polymers {
     Polymers { 
         template,
         rules,
+        has_rule,
         translation: reverse_translation,
         count
     }
@@ -119,3 +117,462 @@ fn iterate_40_times(input: &Polymers) -> usize {
     iterate(input, 40)
 }
 
+// Counts modulo M, for step counts where the real numbers would have billions of digits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Mod<const M: u64>(u64);
+
+impl<const M: u64> std::ops::Add for Mod<M> {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Mod((self.0 + other.0) % M)
+    }
+}
+
+impl<const M: u64> std::ops::AddAssign for Mod<M> {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl<const M: u64> std::ops::Mul for Mod<M> {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        Mod((self.0 as u128 * other.0 as u128 % M as u128) as u64)
+    }
+}
+
+impl<const M: u64> std::ops::MulAssign for Mod<M> {
+    fn mul_assign(&mut self, other: Self) {
+        *self = *self * other;
+    }
+}
+
+impl<const M: u64> Zero for Mod<M> {
+    fn zero() -> Self { Mod(0) }
+    fn is_zero(&self) -> bool { self.0 == 0 }
+}
+
+impl<const M: u64> One for Mod<M> {
+    fn one() -> Self { Mod(1 % M) }
+}
+
+impl<const M: u64> std::fmt::Display for Mod<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// Column i says which pairs pair i turns into in one step. Pairs without a rule stay as they are.
+fn transition<T: Scalar + Zero + One>(input: &Polymers) -> DMatrix<T> {
+    let n = input.count * input.count;
+    let mut matrix = DMatrix::<T>::zeros(n, n);
+    for (i, &rule) in input.rules.iter().enumerate() {
+        if !input.has_rule[i] {
+            matrix[(i, i)] = T::one();
+            continue;
+        }
+        let (f, s) = double(i, rule, input.count);
+        matrix[(f, i)] = matrix[(f, i)].clone() + T::one();
+        matrix[(s, i)] = matrix[(s, i)].clone() + T::one();
+    }
+    matrix
+}
+
+// Square and multiply, like `after_n_days_big_matrix` in d6, but only ever applying
+// the squares to the pair vector so the last multiplication is a cheap one
+fn pairs_after<T: Scalar + Zero + One + ClosedAdd + ClosedMul>(input: &Polymers, steps: u64) -> DVector<T> {
+    let mut pairs = DVector::<T>::zeros(input.count * input.count);
+    for pair in input.template.windows(2) {
+        let i = key(pair[0], pair[1], input.count);
+        pairs[i] = pairs[i].clone() + T::one();
+    }
+    let mut square = transition::<T>(input);
+    let mut pow = steps;
+    while pow > 0 {
+        if pow % 2 == 1 {
+            pairs = &square * &pairs;
+        }
+        pow /= 2;
+        if pow > 0 {
+            square = &square * &square;
+        }
+    }
+    pairs
+}
+
+// Every element except the last one starts exactly one pair
+fn elements_from_pairs<T: Scalar + Zero + One>(input: &Polymers, pairs: &DVector<T>) -> Vec<T> {
+    let mut individuals = vec![T::zero(); input.count];
+    for (i, count) in pairs.iter().enumerate() {
+        let (a, _) = unkey(i, input.count);
+        individuals[a] = individuals[a].clone() + count.clone();
+    }
+    let last = *input.template.last().unwrap();
+    individuals[last] = individuals[last].clone() + T::one();
+    individuals
+}
+
+fn big_difference(input: &Polymers, steps: u64) -> BigUint {
+    let pairs = pairs_after::<BigUint>(input, steps);
+    match elements_from_pairs(input, &pairs).into_iter().minmax() {
+        itertools::MinMaxResult::MinMax(a, b) => b - a,
+        _ => unreachable!()
+    }
+}
+
+// Element counts modulo 1e9 + 7
+fn modular_elements(input: &Polymers, steps: u64) -> String {
+    let pairs = pairs_after::<Mod<1_000_000_007>>(input, steps);
+    elements_from_pairs(input, &pairs)
+        .iter()
+        .enumerate()
+        .map(|(i, count)| format!("{}: {}", input.translation[i] as char, count))
+        .join(", ")
+}
+
+#[aoc(day14, part2, Matrix)]
+fn matrix_40_times(input: &Polymers) -> BigUint {
+    big_difference(input, 40)
+}
+
+#[aoc(day14, part2, Modular)]
+fn modular_trillion_times(input: &Polymers) -> String {
+    modular_elements(input, 1_000_000_000_000)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RuleProblem {
+    Malformed(String),
+    // A pair of known elements with nothing to insert between them
+    Missing(String),
+    // The same rule more than once
+    Duplicate(String),
+    // The same pair with different insertions
+    Conflicting(String, Vec<char>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RuleErrors(Vec<RuleProblem>);
+
+impl std::fmt::Display for RuleErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for problem in &self.0 {
+            match problem {
+                RuleProblem::Malformed(line) => writeln!(f, "malformed line {:?}", line)?,
+                RuleProblem::Missing(pair) => writeln!(f, "no rule for {}", pair)?,
+                RuleProblem::Duplicate(pair) => writeln!(f, "duplicate rule for {}", pair)?,
+                RuleProblem::Conflicting(pair, insertions) => {
+                    writeln!(f, "conflicting rules for {}: {:?}", pair, insertions)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuleErrors {}
+
+// Like `polymers`, but refusing input where the rule table would silently fill in element 0
+fn checked_polymers(input: &str) -> Result<Polymers, RuleErrors> {
+    let mut problems = vec![];
+    let mut lines = input.lines();
+    let template = lines.next().unwrap_or("");
+    if !template.is_ascii() {
+        problems.push(RuleProblem::Malformed(template.to_string()));
+    }
+    match lines.next() {
+        Some("") | None => (),
+        Some(line) => problems.push(RuleProblem::Malformed(line.to_string())),
+    }
+    let mut elements: BTreeSet<char> = template.chars().collect();
+    let mut insertions: BTreeMap<String, Vec<char>> = BTreeMap::new();
+    for line in lines {
+        // `polymers` indexes the rules by byte
+        let chars: Vec<char> = line.chars().collect();
+        if chars.len() != 7 || chars[2..6] != [' ', '-', '>', ' '] || !line.is_ascii() {
+            problems.push(RuleProblem::Malformed(line.to_string()));
+            continue;
+        }
+        elements.extend([chars[0], chars[1], chars[6]]);
+        insertions.entry(chars[..2].iter().collect()).or_default().push(chars[6]);
+    }
+    for &a in &elements {
+        for &b in &elements {
+            let pair: String = [a, b].iter().collect();
+            match insertions.get(&pair) {
+                None => problems.push(RuleProblem::Missing(pair)),
+                Some(list) if list.iter().all_equal() && list.len() > 1 => {
+                    problems.push(RuleProblem::Duplicate(pair))
+                },
+                Some(list) if !list.iter().all_equal() => {
+                    problems.push(RuleProblem::Conflicting(pair, list.clone()))
+                },
+                Some(_) => ()
+            }
+        }
+    }
+    if problems.is_empty() {
+        Ok(polymers(input))
+    }
+    else {
+        Err(RuleErrors(problems))
+    }
+}
+
+// How many of each element and each pair the polymer has after some steps
+struct Histogram<T> {
+    elements: BTreeMap<char, T>,
+    pairs: BTreeMap<String, T>,
+}
+
+impl<T: Scalar + Zero + One + ClosedAdd + ClosedMul> Histogram<T> {
+    fn after(input: &Polymers, steps: u64) -> Self {
+        let pair_counts = pairs_after::<T>(input, steps);
+        let name = |i: usize| input.translation[i] as char;
+        let elements = elements_from_pairs(input, &pair_counts)
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| (name(i), count))
+            .collect();
+        let pairs = pair_counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| !count.is_zero())
+            .map(|(i, count)| {
+                let (a, b) = unkey(i, input.count);
+                ([name(a), name(b)].iter().collect(), count.clone())
+            })
+            .collect();
+        Histogram { elements, pairs }
+    }
+}
+
+impl<T: std::fmt::Display> std::fmt::Display for Histogram<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (element, count) in &self.elements {
+            writeln!(f, "{}: {}", element, count)?;
+        }
+        for (pair, count) in &self.pairs {
+            writeln!(f, "{}: {}", pair, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[aoc_generator(day14, part1, Histogram)]
+fn checked_polymers_part1(input: &str) -> Result<Polymers, RuleErrors> {
+    checked_polymers(input)
+}
+
+#[aoc(day14, part1, Histogram)]
+fn histogram_10_times(input: &Polymers) -> Histogram<BigUint> {
+    Histogram::after(input, 10)
+}
+
+// The polymer after some steps, one character at a time. Each template pair is expanded
+// depth first, so only one path down the insertion tree is kept around.
+struct Expansion<'a> {
+    input: &'a Polymers,
+    steps: usize,
+    // Index of the next template pair to expand
+    next_pair: usize,
+    // (pair, steps left), rightmost at the bottom
+    stack: Vec<(usize, usize)>,
+}
+
+impl<'a> Iterator for Expansion<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let input = self.input;
+        loop {
+            match self.stack.pop() {
+                Some((pair, left)) => {
+                    if left == 0 || !input.has_rule[pair] {
+                        let (a, _) = unkey(pair, input.count);
+                        return Some(input.translation[a] as char)
+                    }
+                    let (f, s) = double(pair, input.rules[pair], input.count);
+                    self.stack.push((s, left - 1));
+                    self.stack.push((f, left - 1));
+                },
+                None if self.next_pair + 1 < input.template.len() => {
+                    let (a, b) = (input.template[self.next_pair], input.template[self.next_pair + 1]);
+                    self.stack.push((key(a, b, input.count), self.steps));
+                    self.next_pair += 1;
+                },
+                None if self.next_pair + 1 == input.template.len() => {
+                    self.next_pair += 1;
+                    return Some(input.translation[*input.template.last().unwrap()] as char)
+                },
+                None => return None
+            }
+        }
+    }
+}
+
+impl Polymers {
+    fn expand(&self, steps: usize) -> Expansion<'_> {
+        Expansion { input: self, steps, next_pair: 0, stack: vec![] }
+    }
+}
+
+// Random access into the polymer after some steps, by skipping over whole pair expansions.
+// lengths[s][pair] is how long the pair grows after s steps, not counting its second element.
+struct PolymerIndex<'a> {
+    input: &'a Polymers,
+    lengths: Vec<Vec<u128>>,
+}
+
+impl<'a> PolymerIndex<'a> {
+    fn new(input: &'a Polymers) -> Self {
+        PolymerIndex { input, lengths: vec![vec![1; input.count * input.count]] }
+    }
+
+    fn length(&mut self, pair: usize, steps: usize) -> u128 {
+        while self.lengths.len() <= steps {
+            let last = &self.lengths[self.lengths.len() - 1];
+            let next = (0..last.len())
+                .map(|pair| if self.input.has_rule[pair] {
+                    let (f, s) = double(pair, self.input.rules[pair], self.input.count);
+                    last[f].saturating_add(last[s])
+                } else {
+                    1
+                })
+                .collect();
+            self.lengths.push(next);
+        }
+        self.lengths[steps][pair]
+    }
+
+    fn char_at(&mut self, steps: usize, mut index: u128) -> Option<char> {
+        let input = self.input;
+        for window in input.template.windows(2) {
+            let mut pair = key(window[0], window[1], input.count);
+            let length = self.length(pair, steps);
+            if index >= length {
+                index -= length;
+                continue;
+            }
+            for left in (0..=steps).rev() {
+                if left == 0 || !input.has_rule[pair] {
+                    break;
+                }
+                let (f, s) = double(pair, input.rules[pair], input.count);
+                let first = self.length(f, left - 1);
+                if index < first {
+                    pair = f;
+                }
+                else {
+                    index -= first;
+                    pair = s;
+                }
+            }
+            return Some(input.translation[unkey(pair, input.count).0] as char)
+        }
+        (index == 0).then(|| input.translation[*input.template.last().unwrap()] as char)
+    }
+}
+
+#[aoc(day14, part1, Lazy)]
+fn lazy_10_times(input: &Polymers) -> usize {
+    let counts = input.expand(10).counts();
+    match counts.values().minmax() {
+        itertools::MinMaxResult::MinMax(a, b) => b - a,
+        _ => unreachable!()
+    }
+}
+
+// The middle of the 40 step polymer
+#[aoc(day14, part2, CharAt)]
+fn middle_after_40(input: &Polymers) -> char {
+    let mut index = PolymerIndex::new(input);
+    let len: u128 = input.template
+        .windows(2)
+        .map(|w| index.length(key(w[0], w[1], input.count), 40))
+        .sum::<u128>() + 1;
+    index.char_at(40, len / 2).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+NNCB
+
+CH -> B
+HH -> N
+CB -> H
+NH -> C
+HB -> C
+HC -> B
+HN -> C
+NN -> C
+BH -> H
+NC -> B
+NB -> B
+BN -> B
+BB -> N
+BC -> B
+CC -> N
+CN -> C";
+
+    #[test]
+    fn checked_example() {
+        let input = checked_polymers(EXAMPLE).unwrap();
+        assert_eq!(iterate_10_times(&input), 1588);
+        assert_eq!(matrix_40_times(&input), BigUint::from(2188189693529u64));
+        assert_eq!(lazy_10_times(&input), 1588);
+    }
+
+    #[test]
+    fn malformed_rules() {
+        let malformed = |input: &str, line: &str| {
+            let errors = checked_polymers(input).unwrap_err();
+            assert!(errors.0.contains(&RuleProblem::Malformed(line.to_string())), "{}", errors);
+        };
+        malformed("NN\n\nNé -> C", "Né -> C");
+        malformed("NN\n\nNN => C", "NN => C");
+        malformed("NN\nNN -> C", "NN -> C");
+    }
+
+    #[test]
+    fn missing_and_conflicting_rules() {
+        let errors = checked_polymers("NC\n\nNC -> N\nNC -> C\nNN -> N\nNN -> N\nCN -> C").unwrap_err();
+        assert_eq!(errors.0, vec![
+            RuleProblem::Missing("CC".to_string()),
+            RuleProblem::Conflicting("NC".to_string(), vec!['N', 'C']),
+            RuleProblem::Duplicate("NN".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn pairs_without_rules_stay() {
+        let input = polymers("NNCB\n\nCH -> B\nHH -> N");
+        assert_eq!(input.expand(2).collect::<String>(), "NNCB");
+        let histogram = Histogram::<u64>::after(&input, 2);
+        assert_eq!(histogram.elements.values().sum::<u64>(), 4);
+        assert_eq!(histogram.elements[&'N'], 2);
+        assert_eq!(histogram.pairs.values().sum::<u64>(), 3);
+        assert_eq!(histogram.elements[&'H'], 0);
+    }
+
+    #[test]
+    fn matrix_matches_expansion() {
+        let input = polymers(EXAMPLE);
+        for steps in 0..8 {
+            let histogram = Histogram::<u64>::after(&input, steps);
+            let counts = input.expand(steps as usize).counts();
+            for (element, count) in &histogram.elements {
+                assert_eq!(counts.get(element).copied().unwrap_or(0) as u64, *count);
+            }
+        }
+        let mut index = PolymerIndex::new(&input);
+        let polymer: String = input.expand(4).collect();
+        for (i, c) in polymer.chars().enumerate() {
+            assert_eq!(index.char_at(4, i as u128), Some(c));
+        }
+        assert_eq!(index.char_at(4, polymer.len() as u128), None);
+    }
+}
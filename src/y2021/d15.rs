@@ -29,8 +29,8 @@ impl Grid {
 
     fn x_get(&self, index: usize) -> usize {
         let (x, y) = unkey(index, self.x_width());
-        let extra_weight = x / self.width + y / self.width;
-        (self.ravel[key((x % self.width, y % self.width), self.width)] + extra_weight - 1) % 9 + 1
+        let extra_weight = x / self.height + y / self.width;
+        (self.ravel[key((x % self.height, y % self.width), self.width)] + extra_weight - 1) % 9 + 1
     }
 
     fn adjacent_indices(&self, index: usize) -> Vec<usize> {
@@ -42,7 +42,7 @@ impl Grid {
         if index % width != 0 {
             adj.push(index - 1);
         }
-        if index % self.width != width - 1 {
+        if index % width != width - 1 {
             adj.push(index + 1);
         }
         if index / width != 0 {
@@ -101,7 +101,7 @@ impl PartialOrd for Cost {
 #[aoc(day15, part1)]
 fn djikstra_pathfinding(input: &Grid) -> usize {
     let start = (0, 0);
-    let end = (input.width - 1, input.height - 1);
+    let end = (input.height - 1, input.width - 1);
     let mut values = vec![usize::MAX; input.area()];
     let mut min_heap = BinaryHeap::new();
     values[key(start, input.width)] = 0;
@@ -130,7 +130,7 @@ fn djikstra_pathfinding(input: &Grid) -> usize {
 #[aoc(day15, part2)]
 fn extended_board(input: &Grid) -> usize {
     let start = (0, 0);
-    let end = (input.x_width() - 1, input.x_height() - 1);
+    let end = (input.x_height() - 1, input.x_width() - 1);
     let mut values = vec![usize::MAX; input.x_area()];
     let mut min_heap = BinaryHeap::new();
     values[key(start, input.x_width())] = 0;
@@ -156,3 +156,335 @@ fn extended_board(input: &Grid) -> usize {
     usize::MAX
 }
 
+// Risk past 9 starts over at 1
+fn wrap_nine(risk: usize) -> usize {
+    (risk - 1) % 9 + 1
+}
+
+// Offsets are (dx, dy)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+enum MoveSet {
+    #[default]
+    Orthogonal,
+    Diagonal,
+    Custom(Vec<(isize, isize)>),
+}
+
+impl MoveSet {
+    fn knight() -> Self {
+        MoveSet::Custom(vec![(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)])
+    }
+
+    fn offsets(&self) -> Vec<(isize, isize)> {
+        match self {
+            MoveSet::Orthogonal => vec![(-1, 0), (1, 0), (0, -1), (0, 1)],
+            MoveSet::Diagonal => vec![(-1, 0), (1, 0), (0, -1), (0, 1), (-1, -1), (1, -1), (-1, 1), (1, 1)],
+            MoveSet::Custom(offsets) => offsets.clone(),
+        }
+    }
+
+    // Fewest moves that could cover the distance, ignoring the edges of the board
+    fn min_moves(&self, dx: usize, dy: usize) -> usize {
+        match self {
+            MoveSet::Orthogonal => dx + dy,
+            MoveSet::Diagonal => dx.max(dy),
+            MoveSet::Custom(offsets) => {
+                let reach_x = offsets.iter().map(|&(ox, _)| ox.unsigned_abs()).max().unwrap_or(0);
+                let reach_y = offsets.iter().map(|&(_, oy)| oy.unsigned_abs()).max().unwrap_or(0);
+                let steps = |d: usize, reach: usize| if reach == 0 { 0 } else { d.div_ceil(reach) };
+                steps(dx, reach_x).max(steps(dy, reach_y))
+            }
+        }
+    }
+}
+
+// The grid repeated `tiles` times in both directions, each tile one riskier than the one
+// above it or to the left of it, before `wrap` is applied
+struct Chiton<'a> {
+    grid: &'a Grid,
+    tiles: usize,
+    wrap: fn(usize) -> usize,
+    moves: MoveSet,
+    // Paid on top of the risk whenever a move differs from the one before it
+    turn_cost: usize,
+    // Cells with this risk can't be entered
+    wall: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Route {
+    cost: usize,
+    // (x, y) of every cell from the top left to the bottom right
+    path: Vec<(usize, usize)>,
+}
+
+impl<'a> Chiton<'a> {
+    fn new(grid: &'a Grid, tiles: usize, wrap: fn(usize) -> usize) -> Self {
+        Chiton { grid, tiles, wrap, moves: MoveSet::default(), turn_cost: 0, wall: None }
+    }
+
+    fn moves(mut self, moves: MoveSet) -> Self {
+        self.moves = moves;
+        self
+    }
+
+    fn turn_cost(mut self, cost: usize) -> Self {
+        self.turn_cost = cost;
+        self
+    }
+
+    fn wall(mut self, risk: usize) -> Self {
+        self.wall = Some(risk);
+        self
+    }
+
+    fn width(&self) -> usize {
+        self.grid.width * self.tiles
+    }
+
+    fn height(&self) -> usize {
+        self.grid.height * self.tiles
+    }
+
+    // Cells are numbered row by row, unlike `Grid`
+    fn risk(&self, index: usize) -> usize {
+        let (x, y) = (index % self.width(), index / self.width());
+        let base = self.grid.ravel[(y % self.grid.height) * self.grid.width + x % self.grid.width];
+        let extra = x / self.grid.width + y / self.grid.height;
+        (self.wrap)(base + extra)
+    }
+
+    fn is_wall(&self, index: usize) -> bool {
+        self.wall == Some(self.risk(index))
+    }
+
+    // Every passable cell reachable in one move, along with which move it was
+    fn neighbors(&self, index: usize, offsets: &[(isize, isize)]) -> Vec<(usize, usize)> {
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let (x, y) = (index as isize % width, index as isize / width);
+        offsets
+            .iter()
+            .enumerate()
+            .map(|(m, &(dx, dy))| (m, x + dx, y + dy))
+            .filter(|&(_, x, y)| x >= 0 && x < width && y >= 0 && y < height)
+            .map(|(m, x, y)| ((y * width + x) as usize, m))
+            .filter(|&(adj, _)| !self.is_wall(adj))
+            .collect()
+    }
+
+    // Every move costs at least the smallest passable risk on the board, so the fewest
+    // moves to the end times that never overestimates
+    fn heuristic(&self, index: usize, min_risk: usize) -> usize {
+        let (x, y) = (index % self.width(), index / self.width());
+        self.moves.min_moves(self.width() - 1 - x, self.height() - 1 - y) * min_risk
+    }
+
+    // Searches over (cell, last move) states. Without a turn cost the last move doesn't
+    // matter, so every cell gets a single state.
+    fn solve(&self) -> Option<Route> {
+        let area = self.width() * self.height();
+        let end = area - 1;
+        let offsets = self.moves.offsets();
+        let headings = if self.turn_cost == 0 { 1 } else { offsets.len() + 1 };
+        // Heading 0 is for the start, where no move has been made yet
+        let heading = |m: usize| if self.turn_cost == 0 { 0 } else { m + 1 };
+        let min_risk = (0..area)
+            .filter(|&index| !self.is_wall(index))
+            .map(|index| self.risk(index))
+            .min()
+            .unwrap_or(0);
+        let mut values = vec![usize::MAX; area * headings];
+        let mut previous = vec![usize::MAX; area * headings];
+        let mut min_heap = BinaryHeap::new();
+        values[0] = 0;
+        min_heap.push(Cost { value: self.heuristic(0, min_risk), index: 0 });
+
+        while let Some(Cost { value, index: state }) = min_heap.pop() {
+            let (index, last) = (state / headings, state % headings);
+            if index == end {
+                let mut path = vec![];
+                let mut current = state;
+                while current != usize::MAX {
+                    let cell = current / headings;
+                    path.push((cell % self.width(), cell / self.width()));
+                    current = previous[current];
+                }
+                path.reverse();
+                return Some(Route { cost: values[state], path })
+            }
+            else if value > values[state] + self.heuristic(index, min_risk) {
+                continue;
+            }
+            for (adj_index, m) in self.neighbors(index, &offsets) {
+                let next = heading(m);
+                let turn = if last != 0 && last != next { self.turn_cost } else { 0 };
+                let cost = values[state] + self.risk(adj_index) + turn;
+                let adj_state = adj_index * headings + next;
+                if cost < values[adj_state] {
+                    values[adj_state] = cost;
+                    previous[adj_state] = state;
+                    min_heap.push(Cost { value: cost + self.heuristic(adj_index, min_risk), index: adj_state });
+                }
+            }
+        }
+        None
+    }
+}
+
+#[aoc(day15, part1, AStar)]
+fn a_star(input: &Grid) -> usize {
+    Chiton::new(input, 1, wrap_nine).solve().unwrap().cost
+}
+
+#[aoc(day15, part2, AStar)]
+fn a_star_extended(input: &Grid) -> usize {
+    Chiton::new(input, 5, wrap_nine).solve().unwrap().cost
+}
+
+#[aoc(day15, part1, Diagonal)]
+fn diagonal(input: &Grid) -> usize {
+    Chiton::new(input, 1, wrap_nine)
+        .moves(MoveSet::Diagonal)
+        .solve()
+        .map_or(usize::MAX, |route| route.cost)
+}
+
+#[aoc(day15, part1, Knight)]
+fn knight(input: &Grid) -> usize {
+    Chiton::new(input, 1, wrap_nine)
+        .moves(MoveSet::knight())
+        .solve()
+        .map_or(usize::MAX, |route| route.cost)
+}
+
+// Nines are walls, and every turn costs as much as the riskiest cell
+#[aoc(day15, part2, Walls)]
+fn walls(input: &Grid) -> usize {
+    Chiton::new(input, 5, wrap_nine)
+        .wall(9)
+        .turn_cost(9)
+        .solve()
+        .map_or(usize::MAX, |route| route.cost)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+1163751742
+1381373672
+2136511328
+3694931569
+7463417111
+1319128137
+1359912421
+3125421639
+1293138521
+2311944581";
+
+    // The total risk of entering every cell after the first
+    fn path_risk(chiton: &Chiton, path: &[(usize, usize)]) -> usize {
+        path.iter().skip(1).map(|&(x, y)| chiton.risk(y * chiton.width() + x)).sum()
+    }
+
+    // The tiled board written out in full, to check `Chiton::risk` against
+    fn tiled(grid: &Grid, tiles: usize) -> Grid {
+        let (width, height) = (grid.width * tiles, grid.height * tiles);
+        let ravel = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let base = grid.ravel[(y % grid.height) * grid.width + x % grid.width];
+                wrap_nine(base + x / grid.width + y / grid.height)
+            })
+            .collect();
+        Grid { ravel, width, height }
+    }
+
+    #[test]
+    fn example() {
+        let grid = generator(EXAMPLE);
+        assert_eq!(djikstra_pathfinding(&grid), 40);
+        assert_eq!(extended_board(&grid), 315);
+        assert_eq!(a_star(&grid), 40);
+        assert_eq!(a_star_extended(&grid), 315);
+    }
+
+    #[test]
+    fn route_is_a_path() {
+        let grid = generator(EXAMPLE);
+        for tiles in [1, 5] {
+            let chiton = Chiton::new(&grid, tiles, wrap_nine);
+            let route = chiton.solve().unwrap();
+            assert_eq!(route.path.first(), Some(&(0, 0)));
+            assert_eq!(route.path.last(), Some(&(chiton.width() - 1, chiton.height() - 1)));
+            for step in route.path.windows(2) {
+                let (dx, dy) = (step[0].0.abs_diff(step[1].0), step[0].1.abs_diff(step[1].1));
+                assert_eq!(dx + dy, 1);
+            }
+            assert_eq!(path_risk(&chiton, &route.path), route.cost);
+        }
+    }
+
+    #[test]
+    fn rectangular_grids() {
+        let grid = generator("19\n11\n91");
+        let route = Chiton::new(&grid, 1, wrap_nine).solve().unwrap();
+        assert_eq!(route, Route { cost: 3, path: vec![(0, 0), (0, 1), (1, 1), (1, 2)] });
+        for input in ["19\n11\n91", "1163751742\n1381373672\n2136511328"] {
+            let grid = generator(input);
+            for tiles in [2, 3, 5] {
+                let full = tiled(&grid, tiles);
+                let cost = Chiton::new(&grid, tiles, wrap_nine).solve().unwrap().cost;
+                assert_eq!(cost, Chiton::new(&full, 1, wrap_nine).solve().unwrap().cost);
+            }
+        }
+    }
+
+    #[test]
+    fn default_moves_are_orthogonal() {
+        for input in [EXAMPLE, "199\n919\n991", "19111\n11191"] {
+            let grid = generator(input);
+            let chiton = Chiton::new(&grid, 1, wrap_nine);
+            assert_eq!(chiton.moves, MoveSet::Orthogonal);
+            assert_eq!(chiton.solve().unwrap().cost, djikstra_pathfinding(&grid), "{}", input);
+        }
+        // Diagonal moves would only cost 2, along the ones
+        let grid = generator("199\n919\n991");
+        assert_eq!(Chiton::new(&grid, 1, wrap_nine).solve().unwrap().cost, 20);
+    }
+
+    #[test]
+    fn diagonal_and_knight_moves() {
+        let grid = generator("199\n919\n991");
+        assert_eq!(a_star(&grid), 20);
+        let route = Chiton::new(&grid, 1, wrap_nine).moves(MoveSet::Diagonal).solve();
+        assert_eq!(route, Some(Route { cost: 2, path: vec![(0, 0), (1, 1), (2, 2)] }));
+        // (0, 0), (1, 2), (2, 0), (0, 1), (2, 2) is the only way there
+        let grid = generator("111\n111\n111");
+        assert_eq!(knight(&grid), 4);
+        assert_eq!(MoveSet::knight().min_moves(5, 3), 3);
+        assert!(diagonal(&generator(EXAMPLE)) <= 40);
+    }
+
+    #[test]
+    fn turn_cost() {
+        let grid = generator("111\n111\n111");
+        let route = Chiton::new(&grid, 1, wrap_nine).turn_cost(5).solve().unwrap();
+        assert_eq!(route.cost, 9);
+        let step = |a: (usize, usize), b: (usize, usize)| (b.0 as isize - a.0 as isize, b.1 as isize - a.1 as isize);
+        let turns = route.path
+            .windows(3)
+            .filter(|w| step(w[0], w[1]) != step(w[1], w[2]))
+            .count();
+        assert_eq!(turns, 1);
+    }
+
+    #[test]
+    fn walls() {
+        let route = Chiton::new(&generator("19\n11"), 1, wrap_nine).wall(9).solve();
+        assert_eq!(route, Some(Route { cost: 2, path: vec![(0, 0), (0, 1), (1, 1)] }));
+        assert_eq!(Chiton::new(&generator("19\n91"), 1, wrap_nine).wall(9).solve(), None);
+        assert_eq!(super::walls(&generator("19\n91")), usize::MAX);
+    }
+}
@@ -1,207 +1,1100 @@
+use std::collections::VecDeque;
+use std::io::{self, Read};
+
+use num_bigint::BigUint;
+use num_traits::{One, Zero};
+
 // Why not
 type Bit = bool;
 
-const MAX_PACKET_DEPTH: usize = 32;
-const MAX_CHILD_COUNT: usize = 64;
+// Reads bits most significant first, refilling a 64-bit buffer a word at a time
+#[derive(Clone)]
+struct BitReader {
+    bytes: Vec<u8>,
+    // Length in bits, which may end halfway through the last byte
+    length: usize,
+    position: usize,
+    next_byte: usize,
+    // The low `buffered` bits are the next ones to be read
+    buffer: u64,
+    buffered: usize,
+}
+
+impl BitReader {
+    fn new(bytes: Vec<u8>, length: usize) -> Self {
+        Self { bytes, length, position: 0, next_byte: 0, buffer: 0, buffered: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.length - self.position
+    }
+
+    fn refill(&mut self) {
+        let end = self.bytes.len().min(self.next_byte + 8);
+        self.buffer = self.bytes[self.next_byte..end].iter().fold(0, |n, &b| n << 8 | b as u64);
+        self.buffered = 8 * (end - self.next_byte);
+        self.next_byte = end;
+    }
+
+    // Up to 64 bits as an integer
+    fn read(&mut self, count: usize) -> Result<u64, StreamError> {
+        assert!(count <= 64, "can't read {} bits at once", count);
+        if count > self.remaining() {
+            return Err(StreamError::UnexpectedEnd)
+        }
+        let mut value = 0;
+        let mut needed = count;
+        while needed > 0 {
+            if self.buffered == 0 {
+                self.refill();
+            }
+            let taken = needed.min(self.buffered);
+            let mask = if taken == 64 { u64::MAX } else { (1 << taken) - 1 };
+            let bits = self.buffer >> (self.buffered - taken) & mask;
+            value = if taken == 64 { bits } else { value << taken | bits };
+            self.buffered -= taken;
+            needed -= taken;
+        }
+        self.position += count;
+        Ok(value)
+    }
+}
 
 #[derive(Clone)]
 struct BitStream {
-    inner: Vec<Bit>,
-    position: usize,
-    // Stack
-    packet_lengths: [usize; MAX_PACKET_DEPTH],
-    packet_depth: usize
+    reader: BitReader,
+    // Bits consumed by every packet being read, innermost last
+    packet_lengths: Vec<usize>,
 }
 
 impl BitStream {
-    fn new(stream: Vec<Bit>) -> Self {
+    fn new(reader: BitReader) -> Self {
         Self {
-            inner: stream,
-            position: 0,
-            packet_lengths: [0; MAX_PACKET_DEPTH],
-            packet_depth: 1
+            reader,
+            packet_lengths: vec![0],
         }
     }
-    fn read(&mut self, count: usize) -> &[Bit] {
-        self.consume(count as usize);
-        let bits = &self.inner[self.position..self.position + count];
-        self.position += count;
-        bits
+
+    // Up to 64 bits, most significant first, counted against every open packet
+    fn read(&mut self, count: usize) -> Result<u64, StreamError> {
+        self.consume(count);
+        self.reader.read(count)
     }
 
-    fn read_bit(&mut self) -> Bit {
-        self.read(1)[0]
+    fn read_bit(&mut self) -> Result<Bit, StreamError> {
+        Ok(self.read(1)? == 1)
     }
 
-    fn read_int(&mut self, count: usize) -> usize {
-        self.read(count)
-            .iter()
-            .rev()
-            .enumerate()
-            .fold(0usize, |n, (i, &bit)| n | (bit as usize) << i as usize)
+    fn read_int(&mut self, count: usize) -> Result<usize, StreamError> {
+        Ok(self.read(count)? as usize)
     }
 
     fn initialize_packet(&mut self) {
-        self.packet_depth += 1;
+        self.packet_lengths.push(0);
     }
     
     fn consume(&mut self, bits: usize) {
-        self.packet_lengths[self.packet_depth - 1] += bits;
+        *self.packet_lengths.last_mut().unwrap() += bits;
     }
     
     fn consumed(&self) -> usize {
-        self.packet_lengths[self.packet_depth - 1]
+        *self.packet_lengths.last().unwrap()
     }
     
     fn finalize_packet(&mut self) {
-        let consumed = self.packet_lengths[self.packet_depth - 1];
-        self.packet_lengths[self.packet_depth - 1] = 0;
-        self.packet_depth -= 1;
+        let consumed = self.packet_lengths.pop().unwrap();
         self.consume(consumed);
     }
 
-    fn close(&mut self) {
-        let all_trailing_zeroes = self.inner[self.position..].iter().all(|&b| !b);
-        assert!(all_trailing_zeroes);
-        self.position = self.inner.len();
+    fn close(&mut self) -> Result<(), StreamError> {
+        let position = self.reader.position;
+        let mut all_trailing_zeroes = true;
+        while self.reader.remaining() > 0 {
+            let count = self.reader.remaining().min(64);
+            all_trailing_zeroes &= self.reader.read(count)? == 0;
+        }
+        if all_trailing_zeroes {
+            Ok(())
+        }
+        else {
+            Err(StreamError::TrailingData { position })
+        }
+    }
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        _ => None
     }
 }
 
 #[aoc_generator(day16)]
-fn generator(input: &str) -> BitStream {
-    let stream = input
-        .trim()
-        .as_bytes()
-        .iter()
-        .map(|&c| match c {
-            b'0'..=b'9' => c - b'0',
-            b'A'..=b'F' => c - b'A' + 10,
-            b'a'..=b'f' => c - b'a' + 10,
-            _ => unreachable!()
-        })
-        .flat_map(|c| 
-            [(c & 8) >> 3, (c & 4) >> 2, (c & 2) >> 1, c & 1] 
-        )
-        .map(|bit| bit == 1)
-        .collect();
-    
-    BitStream::new(stream)
+fn generator(input: &str) -> Result<BitStream, StreamError> {
+    let digits = input.trim().as_bytes();
+    let nibble = |c: u8| hex_digit(c).ok_or(StreamError::InvalidDigit(c));
+    // Two hex digits to a byte, with a zero nibble after an odd last digit
+    let bytes = digits
+        .chunks(2)
+        .map(|pair| Ok(nibble(pair[0])? << 4 | pair.get(1).map_or(Ok(0), |&c| nibble(c))?))
+        .collect::<Result<_, StreamError>>()?;
+
+    Ok(BitStream::new(BitReader::new(bytes, 4 * digits.len())))
 }
 
 impl BitStream {
-    fn version_packet(&mut self) -> usize {
-        self.initialize_packet();
-        let mut version_sum = self.read_int(3);
-        let type_id = self.read_int(3);
-
-        match type_id {
-            // Literal
-            4 => {
-                while self.read_bit() {
-                    // 1-prefixed group
-                    self.read_int(4);
-                }
-                // 0-prefixed group
-                self.read_int(4);
-            }
-            // Operator packets
-            _ => {
-                if self.read_bit() {
-                    let packet_count = self.read_int(11);
-                    for _ in 0..packet_count {
-                        version_sum += self.version_packet();
-                    }
-                }
-                else {
-                    // self.consumed() always returns 22 here
-                    let packet_length = self.read_int(15) + self.consumed();
-                    while self.consumed() < packet_length {
-                        version_sum += self.version_packet();
-                    }
+    fn version_packet(&mut self) -> Result<usize, StreamError> {
+        Ok(self.packet()?.version_sum())
+    }
+
+    fn value_packet(&mut self) -> Result<BigUint, EvalError> {
+        self.packet()?.evaluate()
+    }
+}
+
+#[aoc(day16, part1)]
+fn version_sums(input: &BitStream) -> Result<usize, StreamError> {
+    let mut stream = input.to_owned();
+    let ver = stream.version_packet()?;
+    stream.close()?;
+    Ok(ver)
+}
+
+#[aoc(day16, part2)]
+fn decode_packet(input: &BitStream) -> Result<BigUint, EvalError> {
+    let mut stream = input.to_owned();
+    let value = stream.value_packet()?;
+    stream.close()?;
+    Ok(value)
+}
+
+// Length type ID 0 gives the total length of the children in bits, 1 gives their count
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LengthType {
+    Bits,
+    Count,
+}
+
+// Clone and PartialEq walk the tree instead of recursing, like everything else on packets
+#[derive(Debug, Eq)]
+enum Packet {
+    Literal { version: usize, value: BigUint },
+    Operator { version: usize, type_id: usize, length_type: LengthType, children: Vec<Packet> },
+}
+
+// Drops children one at a time, so deeply nested packets don't overflow the stack
+impl Drop for Packet {
+    fn drop(&mut self) {
+        if let Packet::Operator { children, .. } = self {
+            let mut stack = std::mem::take(children);
+            while let Some(mut packet) = stack.pop() {
+                if let Packet::Operator { children, .. } = &mut packet {
+                    stack.append(children);
                 }
             }
         }
-        self.finalize_packet();
-        version_sum
     }
+}
 
-    fn value_packet(&mut self) -> usize {
-        self.initialize_packet();
-        self.read_int(3); // version
-        let type_id = self.read_int(3);
-    
-        let value = if type_id == 4 {
-            // Literal
-            let mut int = 0;
-            while self.read_bit() {
-                // 1-prefixed group
-                let group = self.read_int(4);
-                int <<= 4;
-                int |= group;
-            }
-            // 0-prefixed group
-            let group = self.read_int(4);
-            int <<= 4;
-            int |= group;
-            int
-        }
-        // Operator packets
-        else {
-            let mut values = [0; MAX_CHILD_COUNT];
-            let mut length = 0;
-            // length type ID
-            if self.read_bit() {
-                length = self.read_int(11);
-                for i in 0..length {
-                    values[i] = self.value_packet();
+// An operator whose children are still being read
+struct OpenOperator {
+    version: usize,
+    type_id: usize,
+    length_type: LengthType,
+    // How many children there are, or the consumed length they end at
+    length: usize,
+    children: Vec<Packet>,
+}
+
+impl OpenOperator {
+    fn is_complete(&self, consumed: usize) -> bool {
+        match self.length_type {
+            LengthType::Count => self.children.len() == self.length,
+            LengthType::Bits => consumed >= self.length,
+        }
+    }
+
+    fn close(self) -> Packet {
+        let OpenOperator { version, type_id, length_type, children, .. } = self;
+        Packet::Operator { version, type_id, length_type, children }
+    }
+}
+
+impl BitStream {
+    // Keeps its own stack of operators, so there is no limit on how deeply packets nest
+    fn packet(&mut self) -> Result<Packet, StreamError> {
+        let mut open: Vec<OpenOperator> = vec![];
+        loop {
+            self.initialize_packet();
+            let version = self.read_int(3)?;
+            let type_id = self.read_int(3)?;
+
+            if type_id == 4 {
+                let mut value = BigUint::zero();
+                while self.read_bit()? {
+                    value = value << 4 | BigUint::from(self.read_int(4)?);
+                }
+                value = value << 4 | BigUint::from(self.read_int(4)?);
+                self.finalize_packet();
+                let literal = Packet::Literal { version, value };
+                match open.last_mut() {
+                    Some(parent) => parent.children.push(literal),
+                    None => return Ok(literal),
                 }
             }
             else {
-                // self.consumed() always returns 22 here
-                let packet_length = self.read_int(15) + self.consumed();
-                // Special case, only 2 packets required
-                if type_id >= 5 {
-                    values[0] = self.value_packet();
-                    values[1] = self.value_packet();
+                let (length_type, length) = if self.read_bit()? {
+                    (LengthType::Count, self.read_int(11)?)
                 }
                 else {
-                    while self.consumed() < packet_length {
-                        values[length] = self.value_packet();
-                        length += 1;
+                    (LengthType::Bits, self.read_int(15)? + self.consumed())
+                };
+                open.push(OpenOperator { version, type_id, length_type, length, children: vec![] });
+            }
+
+            // The innermost operator is the packet being consumed, so `consumed` is its own length
+            while let Some(operator) = open.last() {
+                let consumed = self.consumed();
+                if operator.length_type == LengthType::Bits && consumed > operator.length {
+                    let end = self.reader.position - consumed + operator.length;
+                    return Err(StreamError::Overrun { end })
+                }
+                if !operator.is_complete(consumed) {
+                    break;
+                }
+                let packet = open.pop().unwrap().close();
+                self.finalize_packet();
+                match open.last_mut() {
+                    Some(parent) => parent.children.push(packet),
+                    None => return Ok(packet),
+                }
+            }
+        }
+    }
+}
+
+fn parse(input: &BitStream) -> Result<Packet, StreamError> {
+    let mut stream = input.to_owned();
+    let packet = stream.packet()?;
+    stream.close()?;
+    Ok(packet)
+}
+
+// Every packet is entered, then its children are walked, then it's left
+#[derive(Clone, Copy)]
+enum Visit<'a> {
+    Enter(&'a Packet),
+    Leave(&'a Packet),
+}
+
+impl Packet {
+    fn version(&self) -> usize {
+        match self {
+            Packet::Literal { version, .. } | Packet::Operator { version, .. } => *version,
+        }
+    }
+
+    // Depth first, with a stack instead of recursion
+    fn walk(&self) -> impl Iterator<Item=Visit<'_>> {
+        let mut stack = vec![Visit::Enter(self)];
+        std::iter::from_fn(move || {
+            let visit = stack.pop()?;
+            if let Visit::Enter(packet) = visit {
+                stack.push(Visit::Leave(packet));
+                if let Packet::Operator { children, .. } = packet {
+                    stack.extend(children.iter().rev().map(Visit::Enter));
+                }
+            }
+            Some(visit)
+        })
+    }
+
+    fn version_sum(&self) -> usize {
+        self.walk()
+            .filter_map(|visit| match visit {
+                Visit::Enter(packet) => Some(packet.version()),
+                Visit::Leave(_) => None,
+            })
+            .sum()
+    }
+
+    fn evaluate(&self) -> Result<BigUint, EvalError> {
+        // The operands of every open operator, below the single result
+        let mut operands: Vec<Vec<BigUint>> = vec![vec![]];
+        for visit in self.walk() {
+            match visit {
+                Visit::Enter(Packet::Literal { value, .. }) => operands.last_mut().unwrap().push(value.clone()),
+                Visit::Enter(Packet::Operator { .. }) => operands.push(vec![]),
+                Visit::Leave(Packet::Literal { .. }) => (),
+                Visit::Leave(Packet::Operator { type_id, .. }) => {
+                    let value = apply(*type_id, operands.pop().unwrap())?;
+                    operands.last_mut().unwrap().push(value);
+                }
+            }
+        }
+        Ok(operands.pop().unwrap().pop().unwrap())
+    }
+}
+
+fn apply(type_id: usize, values: Vec<BigUint>) -> Result<BigUint, EvalError> {
+    let truth = |b: bool| if b { BigUint::one() } else { BigUint::zero() };
+    match type_id {
+        0 => Ok(values.into_iter().sum()),
+        1 => Ok(values.into_iter().product()),
+        2 => values.iter().min().cloned().ok_or(EvalError::Operands { type_id, found: 0 }),
+        3 => values.iter().max().cloned().ok_or(EvalError::Operands { type_id, found: 0 }),
+        5..=7 if values.len() != 2 => Err(EvalError::Operands { type_id, found: values.len() }),
+        5 => Ok(truth(values[0] > values[1])),
+        6 => Ok(truth(values[0] < values[1])),
+        7 => Ok(truth(values[0] == values[1])),
+        _ => Err(EvalError::UnknownType(type_id))
+    }
+}
+
+#[derive(Debug)]
+enum EvalError {
+    // Comparisons take exactly two operands, minimum and maximum at least one
+    Operands { type_id: usize, found: usize },
+    // An operator with the literal type ID, which the parser never produces
+    UnknownType(usize),
+    // The transmission couldn't be read in the first place
+    Transmission(StreamError),
+    // Or couldn't be written back out
+    Encoding(EncodeError),
+}
+
+impl From<StreamError> for EvalError {
+    fn from(error: StreamError) -> Self {
+        EvalError::Transmission(error)
+    }
+}
+
+impl From<EncodeError> for EvalError {
+    fn from(error: EncodeError) -> Self {
+        EvalError::Encoding(error)
+    }
+}
+
+impl std::fmt::Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvalError::Operands { type_id, found } => {
+                let expected = if *type_id >= 5 { "exactly two operands" } else { "at least one operand" };
+                write!(f, "{} takes {}, found {}", operator_name(*type_id), expected, found)
+            }
+            EvalError::UnknownType(type_id) => write!(f, "no operator has type ID {}", type_id),
+            EvalError::Transmission(error) => write!(f, "{}", error),
+            EvalError::Encoding(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+fn operator_name(type_id: usize) -> &'static str {
+    match type_id {
+        0 => "sum",
+        1 => "product",
+        2 => "min",
+        3 => "max",
+        5 => "gt",
+        6 => "lt",
+        7 => "eq",
+        _ => "?"
+    }
+}
+
+// S-expressions like `(sum v3 (lit 5) (lt (lit 1) (lit 2)))`, where versions of 0 are left out
+impl std::fmt::Display for Packet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, visit) in self.walk().enumerate() {
+            match visit {
+                Visit::Enter(packet) => {
+                    if i != 0 {
+                        write!(f, " ")?;
+                    }
+                    match packet {
+                        Packet::Literal { .. } => write!(f, "(lit")?,
+                        Packet::Operator { type_id, .. } => write!(f, "({}", operator_name(*type_id))?,
+                    }
+                    if packet.version() != 0 {
+                        write!(f, " v{}", packet.version())?;
+                    }
+                    if let Packet::Literal { value, .. } = packet {
+                        write!(f, " {}", value)?;
                     }
                 }
+                Visit::Leave(_) => write!(f, ")")?,
             }
-            match type_id {
-                0 => values[..length].iter().sum(),
-                1 => values[..length].iter().product(),
-                2 => *values[..length].iter().min().unwrap(),
-                3 => *values[..length].iter().max().unwrap(),
-                4 => unreachable!(), // literal
-                5 => (values[0] > values[1]) as usize,
-                6 => (values[0] < values[1]) as usize,
-                7 => (values[0] == values[1]) as usize,
-                _ => unreachable!()
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum EncodeError {
+    // Versions and type IDs only get three bits
+    Version(usize),
+    TypeId(usize),
+    // An operator with the literal type ID
+    OperatorAsLiteral,
+    // Length type 1 has 11 bits for the number of children
+    TooManyChildren(usize),
+    // Length type 0 has 15 bits for the length of the children
+    TooLong(usize),
+}
+
+impl std::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EncodeError::Version(version) => write!(f, "version {} doesn't fit in 3 bits", version),
+            EncodeError::TypeId(type_id) => write!(f, "type ID {} doesn't fit in 3 bits", type_id),
+            EncodeError::OperatorAsLiteral => write!(f, "an operator can't have the literal type ID 4"),
+            EncodeError::TooManyChildren(count) => write!(f, "{} children don't fit in 11 bits", count),
+            EncodeError::TooLong(length) => write!(f, "{} bits of children don't fit in 15 bits", length),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+fn push_int(bits: &mut Vec<Bit>, n: usize, count: usize) {
+    assert!(n >> count == 0, "{} doesn't fit in {} bits", n, count);
+    bits.extend((0..count).rev().map(|i| n >> i & 1 == 1));
+}
+
+fn push_header(bits: &mut Vec<Bit>, version: usize, type_id: usize) -> Result<(), EncodeError> {
+    if version > 7 {
+        return Err(EncodeError::Version(version))
+    }
+    if type_id > 7 {
+        return Err(EncodeError::TypeId(type_id))
+    }
+    push_int(bits, version, 3);
+    push_int(bits, type_id, 3);
+    Ok(())
+}
+
+impl Packet {
+    fn encode_into(&self, bits: &mut Vec<Bit>) -> Result<(), EncodeError> {
+        // Where the length of every open length type 0 operator goes, once its children are written
+        let mut lengths = vec![];
+        for visit in self.walk() {
+            match visit {
+                Visit::Enter(Packet::Literal { version, value }) => {
+                    push_header(bits, *version, 4)?;
+                    let nibbles = value.to_radix_be(16);
+                    for (i, &nibble) in nibbles.iter().enumerate() {
+                        bits.push(i != nibbles.len() - 1);
+                        push_int(bits, nibble as usize, 4);
+                    }
+                }
+                Visit::Enter(Packet::Operator { type_id: 4, .. }) => return Err(EncodeError::OperatorAsLiteral),
+                Visit::Enter(Packet::Operator { version, type_id, length_type, children }) => {
+                    push_header(bits, *version, *type_id)?;
+                    match length_type {
+                        LengthType::Count => {
+                            if children.len() >= 1 << 11 {
+                                return Err(EncodeError::TooManyChildren(children.len()))
+                            }
+                            bits.push(true);
+                            push_int(bits, children.len(), 11);
+                        }
+                        LengthType::Bits => {
+                            bits.push(false);
+                            lengths.push(bits.len());
+                            push_int(bits, 0, 15);
+                        }
+                    }
+                }
+                Visit::Leave(Packet::Operator { length_type: LengthType::Bits, .. }) => {
+                    let start = lengths.pop().unwrap();
+                    let length = bits.len() - start - 15;
+                    if length >= 1 << 15 {
+                        return Err(EncodeError::TooLong(length))
+                    }
+                    for (i, bit) in bits[start..start + 15].iter_mut().enumerate() {
+                        *bit = length >> (14 - i) & 1 == 1;
+                    }
+                }
+                Visit::Leave(_) => (),
             }
-        };
-    
-        self.finalize_packet();
-        value
+        }
+        Ok(())
+    }
+
+    // Hex digits, padded with zeroes. Every packet keeps its own length type,
+    // so `decode(&packet.encode()?)` gives `packet` back
+    fn encode(&self) -> Result<String, EncodeError> {
+        let mut bits = vec![];
+        self.encode_into(&mut bits)?;
+        Ok(bits
+            .chunks(4)
+            .map(|nibble| {
+                let digit = (0..4).fold(0, |n, i| n << 1 | nibble.get(i).copied().unwrap_or(false) as u32);
+                std::char::from_digit(digit, 16).unwrap().to_ascii_uppercase()
+            })
+            .collect())
+    }
+
+    // The same tree with every operator using the given length type
+    fn with_length_type(&self, length_type: LengthType) -> Packet {
+        self.copy(Some(length_type))
+    }
+
+    // The same tree, with every operator keeping its own length type unless one is given
+    fn copy(&self, length_type: Option<LengthType>) -> Packet {
+        // The children of every open operator so far, below the single result
+        let mut copies: Vec<Vec<Packet>> = vec![vec![]];
+        for visit in self.walk() {
+            match visit {
+                Visit::Enter(Packet::Literal { version, value }) => {
+                    let literal = Packet::Literal { version: *version, value: value.clone() };
+                    copies.last_mut().unwrap().push(literal);
+                }
+                Visit::Enter(Packet::Operator { .. }) => copies.push(vec![]),
+                Visit::Leave(Packet::Literal { .. }) => (),
+                Visit::Leave(Packet::Operator { version, type_id, length_type: own, .. }) => {
+                    let children = copies.pop().unwrap();
+                    let length_type = length_type.unwrap_or(*own);
+                    let operator = Packet::Operator { version: *version, type_id: *type_id, length_type, children };
+                    copies.last_mut().unwrap().push(operator);
+                }
+            }
+        }
+        copies.pop().unwrap().pop().unwrap()
     }
 }
 
-#[aoc(day16, part1)]
-fn version_sums(input: &BitStream) -> usize {
-    let mut stream = input.to_owned();
-    let ver = stream.version_packet();
-    stream.close();
-    ver
+impl Clone for Packet {
+    fn clone(&self) -> Self {
+        self.copy(None)
+    }
 }
 
-#[aoc(day16, part2)]
-fn decode_packet(input: &BitStream) -> usize {
-    let mut stream = input.to_owned();
-    let value = stream.value_packet();
-    stream.close();
-    value
+// Both trees are walked side by side, so once every pair of packets matches with the same
+// number of children, the trees have the same shape too
+impl PartialEq for Packet {
+    fn eq(&self, other: &Self) -> bool {
+        self.walk().zip(other.walk()).all(|visits| match visits {
+            (Visit::Enter(a), Visit::Enter(b)) => match (a, b) {
+                (Packet::Literal { version, value }, Packet::Literal { version: v, value: x }) => {
+                    version == v && value == x
+                }
+                (
+                    Packet::Operator { version, type_id, length_type, children },
+                    Packet::Operator { version: v, type_id: t, length_type: l, children: c },
+                ) => version == v && type_id == t && length_type == l && children.len() == c.len(),
+                _ => false,
+            },
+            (Visit::Leave(_), Visit::Leave(_)) => true,
+            _ => false,
+        })
+    }
+}
+
+fn decode(hex: &str) -> Result<Packet, StreamError> {
+    parse(&generator(hex)?)
+}
+
+#[aoc(day16, part1, Ast)]
+fn ast_version_sum(input: &BitStream) -> Result<usize, StreamError> {
+    Ok(parse(input)?.version_sum())
+}
+
+#[aoc(day16, part2, Ast)]
+fn ast_evaluate(input: &BitStream) -> Result<BigUint, EvalError> {
+    parse(input)?.evaluate()
+}
+
+#[aoc(day16, part2, Pretty)]
+fn pretty(input: &BitStream) -> Result<Packet, StreamError> {
+    parse(input)
+}
+
+// Re-encodes the transmission with the other length type everywhere, then decodes it again
+#[aoc(day16, part2, RoundTrip)]
+fn round_trip(input: &BitStream) -> Result<BigUint, EvalError> {
+    let packet = parse(input)?;
+    let flipped = match packet {
+        Packet::Operator { length_type: LengthType::Bits, .. } => packet.with_length_type(LengthType::Count),
+        _ => packet.with_length_type(LengthType::Bits),
+    };
+    decode(&flipped.encode()?)?.evaluate()
+}
+
+#[derive(Debug)]
+enum StreamError {
+    Io(io::Error),
+    InvalidDigit(u8),
+    // The transmission stopped partway through a packet
+    UnexpectedEnd,
+    // Children overran the length in bits of their parent, which ends at this position
+    Overrun { end: usize },
+    // Something other than zeroes follows the outermost packet, starting at this position
+    TrailingData { position: usize },
+}
+
+impl std::fmt::Display for StreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamError::Io(error) => write!(f, "{}", error),
+            StreamError::InvalidDigit(c) => write!(f, "{:?} is not a hex digit", *c as char),
+            StreamError::UnexpectedEnd => write!(f, "the transmission ended partway through a packet"),
+            StreamError::Overrun { end } => write!(f, "a packet runs past the end of its parent at bit {}", end),
+            StreamError::TrailingData { position } => write!(f, "data follows the packet at bit {}", position),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+impl From<io::Error> for StreamError {
+    fn from(error: io::Error) -> Self {
+        StreamError::Io(error)
+    }
+}
+
+// Bits out of hex digits that are read from the source as they're needed
+struct HexReader<R> {
+    source: R,
+    chunk: [u8; 4096],
+    chunk_start: usize,
+    chunk_end: usize,
+    // The low `buffered` bits are the next ones to be read
+    buffer: u128,
+    buffered: usize,
+    position: usize,
+}
+
+impl<R: Read> HexReader<R> {
+    fn new(source: R) -> Self {
+        Self { source, chunk: [0; 4096], chunk_start: 0, chunk_end: 0, buffer: 0, buffered: 0, position: 0 }
+    }
+
+    // Skips whitespace, so a trailing newline is fine
+    fn next_digit(&mut self) -> Result<Option<u8>, StreamError> {
+        loop {
+            if self.chunk_start == self.chunk_end {
+                self.chunk_end = self.source.read(&mut self.chunk)?;
+                self.chunk_start = 0;
+                if self.chunk_end == 0 {
+                    return Ok(None)
+                }
+            }
+            let c = self.chunk[self.chunk_start];
+            self.chunk_start += 1;
+            match hex_digit(c) {
+                Some(digit) => return Ok(Some(digit)),
+                None if c.is_ascii_whitespace() => (),
+                None => return Err(StreamError::InvalidDigit(c))
+            }
+        }
+    }
+
+    // Up to 64 bits as an integer
+    fn read_int(&mut self, count: usize) -> Result<usize, StreamError> {
+        while self.buffered < count {
+            let digit = self.next_digit()?.ok_or(StreamError::UnexpectedEnd)?;
+            self.buffer = self.buffer << 4 | digit as u128;
+            self.buffered += 4;
+        }
+        self.buffered -= count;
+        let value = self.buffer >> self.buffered & ((1 << count) - 1);
+        self.buffer &= (1 << self.buffered) - 1;
+        self.position += count;
+        Ok(value as usize)
+    }
+
+    fn read_bit(&mut self) -> Result<Bit, StreamError> {
+        Ok(self.read_int(1)? == 1)
+    }
+
+    // Everything left has to be zeroes
+    fn close(&mut self) -> Result<(), StreamError> {
+        let mut trailing = self.buffer != 0;
+        while let Some(digit) = self.next_digit()? {
+            trailing |= digit != 0;
+        }
+        if trailing {
+            Err(StreamError::TrailingData { position: self.position })
+        }
+        else {
+            Ok(())
+        }
+    }
+}
+
+// Every packet is a Begin, then its literal or its children, then an End
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Event {
+    Begin { version: usize, type_id: usize },
+    Literal(BigUint),
+    End,
+}
+
+// What is left of an operator whose children are still being read
+enum Remaining {
+    Packets(usize),
+    // Children end at this position
+    Bits(usize),
 }
 
+// Decodes a transmission packet by packet, without reading ahead of the packet it's in
+struct Decoder<R> {
+    reader: HexReader<R>,
+    open: Vec<Remaining>,
+    pending: VecDeque<Event>,
+    started: bool,
+    finished: bool,
+}
+
+impl<R: Read> Decoder<R> {
+    fn new(source: R) -> Self {
+        Self { reader: HexReader::new(source), open: vec![], pending: VecDeque::new(), started: false, finished: false }
+    }
+
+    // The events of the packet starting here, or of the operator just finished
+    fn advance(&mut self) -> Result<(), StreamError> {
+        match self.open.last() {
+            Some(&Remaining::Packets(0)) => {
+                self.open.pop();
+                self.pending.push_back(Event::End);
+                return Ok(())
+            }
+            Some(&Remaining::Bits(end)) if self.reader.position >= end => {
+                if self.reader.position > end {
+                    return Err(StreamError::Overrun { end })
+                }
+                self.open.pop();
+                self.pending.push_back(Event::End);
+                return Ok(())
+            }
+            Some(Remaining::Packets(_)) | Some(Remaining::Bits(_)) => (),
+            None if self.started => {
+                self.finished = true;
+                return self.reader.close()
+            }
+            None => self.started = true,
+        }
+        if let Some(Remaining::Packets(count)) = self.open.last_mut() {
+            *count -= 1;
+        }
+
+        let version = self.reader.read_int(3)?;
+        let type_id = self.reader.read_int(3)?;
+        self.pending.push_back(Event::Begin { version, type_id });
+        if type_id == 4 {
+            let mut value = BigUint::zero();
+            while self.reader.read_bit()? {
+                value = value << 4 | BigUint::from(self.reader.read_int(4)?);
+            }
+            value = value << 4 | BigUint::from(self.reader.read_int(4)?);
+            self.pending.push_back(Event::Literal(value));
+            self.pending.push_back(Event::End);
+        }
+        else if self.reader.read_bit()? {
+            let packet_count = self.reader.read_int(11)?;
+            self.open.push(Remaining::Packets(packet_count));
+        }
+        else {
+            let packet_length = self.reader.read_int(15)?;
+            self.open.push(Remaining::Bits(self.reader.position + packet_length));
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for Decoder<R> {
+    type Item = Result<Event, StreamError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() && !self.finished {
+            if let Err(error) = self.advance() {
+                self.finished = true;
+                return Some(Err(error))
+            }
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+// Evaluates as the events come in, keeping only the operands of the packets still open
+fn evaluate_events<I: Iterator<Item=Result<Event, StreamError>>>(events: I) -> Result<BigUint, EvalError> {
+    let mut open: Vec<(usize, Vec<BigUint>)> = vec![];
+    let mut result = None;
+    for event in events {
+        match event? {
+            Event::Begin { type_id, .. } => open.push((type_id, vec![])),
+            Event::Literal(value) => open.last_mut().unwrap().1.push(value),
+            Event::End => {
+                let (type_id, values) = open.pop().unwrap();
+                let value = if type_id == 4 { values.into_iter().next().unwrap() } else { apply(type_id, values)? };
+                match open.last_mut() {
+                    Some((_, parent)) => parent.push(value),
+                    None => result = Some(value),
+                }
+            }
+        }
+    }
+    result.ok_or(EvalError::Transmission(StreamError::UnexpectedEnd))
+}
+
+#[aoc_generator(day16, part1, Streaming)]
+fn transmission(input: &str) -> String {
+    input.to_string()
+}
+
+#[aoc_generator(day16, part2, Streaming)]
+fn transmission_again(input: &str) -> String {
+    input.to_string()
+}
+
+#[aoc(day16, part1, Streaming)]
+fn streaming_version_sum(input: &str) -> Result<usize, StreamError> {
+    let mut sum = 0;
+    for event in Decoder::new(input.as_bytes()) {
+        if let Event::Begin { version, .. } = event? {
+            sum += version;
+        }
+    }
+    Ok(sum)
+}
+
+#[aoc(day16, part2, Streaming)]
+fn streaming_evaluate(input: &str) -> Result<BigUint, EvalError> {
+    evaluate_events(Decoder::new(input.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERSION_SUMS: [(&str, usize); 4] = [
+        ("8A004A801A8002F478", 16),
+        ("620080001611562C8802118E34", 12),
+        ("C0015000016115A2E0802F182340", 23),
+        ("A0016C880162017C3686B18A3D4780", 31),
+    ];
+
+    const VALUES: [(&str, u32); 8] = [
+        ("C200B40A82", 3),
+        ("04005AC33890", 54),
+        ("880086C3E88112", 7),
+        ("CE00C43D881120", 9),
+        ("D8005AC2A8F0", 1),
+        ("F600BC2D8F", 0),
+        ("9C005AC2F8F0", 0),
+        ("9C0141080250320F1802104A08", 1),
+    ];
+
+    fn literal(value: u32) -> Packet {
+        Packet::Literal { version: 0, value: BigUint::from(value) }
+    }
+
+    fn operator(type_id: usize, length_type: LengthType, children: Vec<Packet>) -> Packet {
+        Packet::Operator { version: 0, type_id, length_type, children }
+    }
+
+    #[test]
+    fn bit_stream_reads() {
+        let mut stream = generator("D2FE28\n").unwrap();
+        assert_eq!(stream.read(3).unwrap(), 6);
+        stream.initialize_packet();
+        assert_eq!(stream.read_int(3).unwrap(), 4);
+        assert!(stream.read_bit().unwrap());
+        assert_eq!(stream.consumed(), 4);
+        stream.finalize_packet();
+        assert_eq!(stream.consumed(), 7);
+        assert_eq!(stream.read(17).unwrap(), 0xFE28);
+
+        // An odd number of digits ends halfway through the last byte
+        let mut stream = generator("0123456789abcdefA").unwrap();
+        assert_eq!(stream.reader.remaining(), 68);
+        assert_eq!(stream.read(64).unwrap(), 0x0123_4567_89AB_CDEF);
+        assert_eq!(stream.read(4).unwrap(), 0xA);
+        assert_eq!(stream.reader.remaining(), 0);
+    }
+
+    #[test]
+    fn examples() {
+        for (hex, sum) in VERSION_SUMS {
+            assert_eq!(version_sums(&generator(hex).unwrap()).unwrap(), sum, "{}", hex);
+            assert_eq!(ast_version_sum(&generator(hex).unwrap()).unwrap(), sum, "{}", hex);
+        }
+        for (hex, value) in VALUES {
+            assert_eq!(decode_packet(&generator(hex).unwrap()).unwrap(), BigUint::from(value), "{}", hex);
+            assert_eq!(ast_evaluate(&generator(hex).unwrap()).unwrap(), BigUint::from(value), "{}", hex);
+            assert_eq!(round_trip(&generator(hex).unwrap()).unwrap(), BigUint::from(value), "{}", hex);
+        }
+    }
+
+    #[test]
+    fn decoded_examples() {
+        assert_eq!(decode("D2FE28").unwrap(), Packet::Literal { version: 6, value: BigUint::from(2021u32) });
+        assert_eq!(decode("38006F45291200").unwrap(), Packet::Operator {
+            version: 1,
+            type_id: 6,
+            length_type: LengthType::Bits,
+            children: vec![
+                Packet::Literal { version: 6, value: BigUint::from(10u32) },
+                Packet::Literal { version: 2, value: BigUint::from(20u32) },
+            ],
+        });
+        let packet = decode("EE00D40C823060").unwrap();
+        assert_eq!(packet.to_string(), "(max v7 (lit v2 1) (lit v4 2) (lit v1 3))");
+    }
+
+    #[test]
+    fn encode_round_trips() {
+        let examples = VERSION_SUMS.iter().map(|&(hex, _)| hex).chain(VALUES.iter().map(|&(hex, _)| hex));
+        for hex in examples.chain(["D2FE28", "38006F45291200", "EE00D40C823060"]) {
+            let packet = decode(hex).unwrap();
+            assert_eq!(decode(&packet.encode().unwrap()).unwrap(), packet, "{}", hex);
+            for length_type in [LengthType::Bits, LengthType::Count] {
+                let packet = packet.with_length_type(length_type);
+                assert_eq!(decode(&packet.encode().unwrap()).unwrap(), packet, "{}", hex);
+            }
+        }
+        assert_eq!(Packet::Literal { version: 6, value: BigUint::from(2021u32) }.encode(), Ok("D2FE28".to_string()));
+        assert_eq!(decode(&literal(0).encode().unwrap()).unwrap(), literal(0));
+    }
+
+    #[test]
+    fn unencodable_packets() {
+        let version = Packet::Literal { version: 8, value: BigUint::zero() };
+        assert_eq!(version.encode(), Err(EncodeError::Version(8)));
+        let type_id = operator(9, LengthType::Count, vec![literal(1)]);
+        assert_eq!(type_id.encode(), Err(EncodeError::TypeId(9)));
+        assert_eq!(operator(4, LengthType::Count, vec![]).encode(), Err(EncodeError::OperatorAsLiteral));
+
+        let children = |count| (0..count).map(|_| literal(0)).collect::<Vec<_>>();
+        assert!(operator(0, LengthType::Count, children(2047)).encode().is_ok());
+        let many = operator(0, LengthType::Count, children(2048));
+        assert_eq!(many.encode(), Err(EncodeError::TooManyChildren(2048)));
+        // Every zero literal takes 11 bits
+        let long = operator(0, LengthType::Bits, children(2978));
+        assert!(long.encode().is_ok());
+        let longer = operator(0, LengthType::Bits, children(2979));
+        assert_eq!(longer.encode(), Err(EncodeError::TooLong(32769)));
+        let nested = operator(0, LengthType::Count, vec![many]);
+        assert_eq!(nested.encode(), Err(EncodeError::TooManyChildren(2048)));
+    }
+
+    #[test]
+    fn packets_compare_by_walking() {
+        let tree = |deep| operator(0, LengthType::Count, vec![literal(1), operator(1, LengthType::Bits, vec![literal(deep)])]);
+        assert_eq!(tree(2), tree(2));
+        assert_eq!(tree(2).clone(), tree(2));
+        assert_ne!(tree(2), tree(3));
+        assert_ne!(tree(2), tree(2).with_length_type(LengthType::Bits));
+        assert_ne!(operator(0, LengthType::Count, vec![literal(1)]), operator(0, LengthType::Count, vec![literal(1), literal(1)]));
+        assert_ne!(operator(0, LengthType::Count, vec![]), literal(0));
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let depth = 20_000;
+        let mut packet = literal(7);
+        for _ in 0..depth {
+            packet = Packet::Operator { version: 1, type_id: 0, length_type: LengthType::Count, children: vec![packet] };
+        }
+        let hex = packet.encode().unwrap();
+        let decoded = decode(&hex).unwrap();
+        assert_eq!(decoded, packet);
+        assert_eq!(packet.clone(), packet);
+        assert_eq!(decoded.encode().unwrap(), hex);
+        assert_eq!(decoded.evaluate().unwrap(), BigUint::from(7u32));
+        assert_eq!(decoded.version_sum(), depth);
+        assert_eq!(streaming_version_sum(&hex).unwrap(), depth);
+        assert_eq!(streaming_evaluate(&hex).unwrap(), BigUint::from(7u32));
+        assert_eq!(decoded.to_string(), "(sum v1 ".repeat(depth) + "(lit 7)" + &")".repeat(depth));
+        // A literal 7 takes 11 bits, and every sum around it another 22
+        let bits = decoded.with_length_type(LengthType::Bits);
+        assert_ne!(bits, decoded);
+        assert_eq!(bits.encode(), Err(EncodeError::TooLong(32769)));
+    }
+
+    // Hands the transmission over a byte at a time
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match (self.0.split_first(), buf.first_mut()) {
+                (Some((&byte, rest)), Some(first)) => {
+                    *first = byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0)
+            }
+        }
+    }
+
+    #[test]
+    fn streaming_events() {
+        let events = |hex: &str| Decoder::new(hex.as_bytes()).collect::<Result<Vec<_>, _>>().unwrap();
+        let literal = |version, value: u32| vec![
+            Event::Begin { version, type_id: 4 },
+            Event::Literal(BigUint::from(value)),
+            Event::End,
+        ];
+        let mut expected = vec![Event::Begin { version: 1, type_id: 6 }];
+        expected.extend(literal(6, 10));
+        expected.extend(literal(2, 20));
+        expected.push(Event::End);
+        assert_eq!(events("38006F45291200"), expected);
+        let mut expected = vec![Event::Begin { version: 7, type_id: 3 }];
+        expected.extend(literal(2, 1));
+        expected.extend(literal(4, 2));
+        expected.extend(literal(1, 3));
+        expected.push(Event::End);
+        assert_eq!(events("EE00D40C823060"), expected);
+    }
+
+    #[test]
+    fn streaming_examples() {
+        for (hex, sum) in VERSION_SUMS {
+            assert_eq!(streaming_version_sum(hex).unwrap(), sum, "{}", hex);
+        }
+        for (hex, value) in VALUES {
+            assert_eq!(streaming_evaluate(hex).unwrap(), BigUint::from(value), "{}", hex);
+            let trickle = format!("{}\n", hex.to_ascii_lowercase());
+            let events = Decoder::new(Trickle(trickle.as_bytes()));
+            assert_eq!(evaluate_events(events).unwrap(), BigUint::from(value), "{}", hex);
+        }
+    }
+
+    #[test]
+    fn transmission_errors() {
+        // Ends partway through the first literal
+        assert!(matches!(decode("38006F45"), Err(StreamError::UnexpectedEnd)));
+        assert!(matches!(streaming_version_sum("38006F45"), Err(StreamError::UnexpectedEnd)));
+        assert!(matches!(decode(""), Err(StreamError::UnexpectedEnd)));
+        assert!(matches!(streaming_evaluate(""), Err(EvalError::Transmission(StreamError::UnexpectedEnd))));
+
+        assert!(matches!(decode("D2FX28"), Err(StreamError::InvalidDigit(b'X'))));
+        assert!(matches!(streaming_version_sum("D2FX28"), Err(StreamError::InvalidDigit(b'X'))));
+
+        assert!(matches!(decode("D2FE2810"), Err(StreamError::TrailingData { position: 21 })));
+        assert!(matches!(streaming_version_sum("D2FE2810"), Err(StreamError::TrailingData { position: 21 })));
+        assert!(streaming_version_sum("D2FE28000").is_ok());
+
+        // Ten bits of children, but the only child is an 11 bit literal
+        assert!(matches!(decode("000028408"), Err(StreamError::Overrun { end: 32 })));
+        assert!(matches!(streaming_version_sum("000028408"), Err(StreamError::Overrun { end: 32 })));
+    }
+
+    #[test]
+    fn pretty_printing() {
+        let packet = Packet::Operator {
+            version: 3,
+            type_id: 0,
+            length_type: LengthType::Count,
+            children: vec![literal(5), operator(6, LengthType::Bits, vec![literal(1), literal(2)])],
+        };
+        assert_eq!(packet.to_string(), "(sum v3 (lit 5) (lt (lit 1) (lit 2)))");
+    }
+}
@@ -1,3 +1,8 @@
+use super::cycle::{self, Outcome};
+
+// Give up on the herds stopping after this many steps
+const MAX_STEPS: usize = 100_000;
+
 #[derive(Debug, Clone, Copy)]
 enum Cell {
     // last step that this cell was modified
@@ -23,55 +28,98 @@ fn generator(input: &str) -> Vec<Vec<Cell>> {
     .collect()
 }
 
-#[aoc(day25, part1)]
-fn entry(input: &[Vec<Cell>]) -> usize {
-    let mut grid = input.to_vec();
-    let mut half_steps = 0;
+// One full step (east then south) after the given number of half steps,
+// returning whether nothing moved
+fn step(grid: &mut [Vec<Cell>], mut half_steps: usize) -> bool {
     let w = grid[0].len();
     let h = grid.len();
-    loop {
-        half_steps += 1;
-        let mut done = true;
-        // double pass because it's christmas
-        for y in 0..h {
-            for x in 0..w {
-                match grid[y][x] {
-                    Cell::East(s) if s < half_steps => {
-                        match grid[y][(x+1)%w] {
-                            Cell::No(t) if t < half_steps => {
-                                done = false;
-                                grid[y][(x+1)%w] = Cell::East(half_steps);
-                                grid[y][x] = Cell::No(half_steps);
-                            }
-                            _ => () // do not move
+    half_steps += 1;
+    let mut done = true;
+    // double pass because it's christmas
+    for y in 0..h {
+        for x in 0..w {
+            match grid[y][x] {
+                Cell::East(s) if s < half_steps => {
+                    match grid[y][(x+1)%w] {
+                        Cell::No(t) if t < half_steps => {
+                            done = false;
+                            grid[y][(x+1)%w] = Cell::East(half_steps);
+                            grid[y][x] = Cell::No(half_steps);
                         }
+                        _ => () // do not move
                     }
-                    _ => () // do not move
                 }
+                _ => () // do not move
             }
         }
-        half_steps += 1;
-        // double pass because it's christmas
-        for x in 0..w {
-            for y in 0..h {
-                match grid[y][x] {
-                    Cell::South(s) if s < half_steps => {
-                        match grid[(y+1)%h][x] {
-                            Cell::No(t) if t < half_steps => {
-                                done = false;
-                                grid[(y+1)%h][x] = Cell::South(half_steps);
-                                grid[y][x] = Cell::No(half_steps);
-                            }
-                            _ => {} // do not move
+    }
+    half_steps += 1;
+    // double pass because it's christmas
+    for x in 0..w {
+        for y in 0..h {
+            match grid[y][x] {
+                Cell::South(s) if s < half_steps => {
+                    match grid[(y+1)%h][x] {
+                        Cell::No(t) if t < half_steps => {
+                            done = false;
+                            grid[(y+1)%h][x] = Cell::South(half_steps);
+                            grid[y][x] = Cell::No(half_steps);
                         }
+                        _ => {} // do not move
                     }
-                    _ => {} // do not move
                 }
+                _ => {} // do not move
             }
         }
-        if done {
-            break half_steps / 2;
-        }
     }
+    done
+}
+
+// The grid without the step stamps, which would make every state look new
+fn layout(grid: &[Vec<Cell>]) -> Vec<u8> {
+    grid.iter().flatten().map(|cell| match cell {
+        Cell::No(_) => b'.',
+        Cell::East(_) => b'>',
+        Cell::South(_) => b'v',
+    }).collect()
 }
 
+fn settle(input: &[Vec<Cell>], max_steps: usize) -> Outcome {
+    let mut state = (input.to_vec(), 0);
+    cycle::run(&mut state, max_steps, |(grid, _)| layout(grid), |(grid, half_steps)| {
+        let done = step(grid, *half_steps);
+        *half_steps += 2;
+        done
+    })
+}
+
+#[aoc(day25, part1)]
+fn entry(input: &[Vec<Cell>]) -> Outcome {
+    settle(input, MAX_STEPS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_settles() {
+        let input = generator("\
+v...>>.vv>
+.vv>>.vv..
+>>.>v>...v
+>>v>>.>.v.
+v>v.vv.v..
+>.>>..v...
+.vv..>.>v.
+v.v..>>v.v
+....v..v.>");
+        assert_eq!(entry(&input), Outcome::Stable(58));
+    }
+
+    #[test]
+    fn lone_herd_cycles() {
+        assert_eq!(entry(&generator(">..")), Outcome::Cycle { start: 0, period: 3 });
+        assert_eq!(settle(&generator(">..\n...\n..v"), 2), Outcome::Limit(2));
+    }
+}
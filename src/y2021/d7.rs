@@ -1,3 +1,6 @@
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+
 use itertools::{Itertools, MinMaxResult};
 
 #[aoc_generator(day7)]
@@ -5,31 +8,271 @@ fn positions(input: &str) -> Vec<usize> {
     input.split(",").map(|n| n.parse().unwrap()).collect()
 }
 
-fn minimize_fuel_consumption(input: &[usize], consumption: fn(usize) -> usize) -> usize {
-    let mut widths = input.to_owned();
-    if let MinMaxResult::MinMax(&min, &max) = input.iter().minmax() {
-        widths.sort_unstable();
-        let counts: Vec<(usize, usize)> = widths
-            .iter()
-            .group_by(|&x| x)
-            .into_iter()
-            .map(|(&n, group)| (n, group.count()))
-            .filter(|&(_, count)| count != 0)
-            .collect();
-        (min..=max).map(|x| {
-            counts.iter().fold(0, |sum, &(n, count)| sum + count * consumption((x as isize - n as isize).abs() as usize))
-        }).min().unwrap()
+// The cheapest positions to align at (every position in the range costs the same), and that cost
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Alignment {
+    positions: RangeInclusive<usize>,
+    fuel: usize,
+}
+
+impl std::fmt::Display for Alignment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (at {}..={})", self.fuel, self.positions.start(), self.positions.end())
+    }
+}
+
+fn distance(a: usize, b: usize) -> usize {
+    (a as isize - b as isize).unsigned_abs()
+}
+
+// Sorted (position, total weight) pairs
+fn group_weights<T: Iterator<Item=(usize, usize)>>(crabs: T) -> Vec<(usize, usize)> {
+    let mut crabs: Vec<_> = crabs.collect();
+    crabs.sort_unstable();
+    crabs
+        .iter()
+        .group_by(|&&(x, _)| x)
+        .into_iter()
+        .map(|(n, group)| (n, group.map(|&(_, weight)| weight).sum()))
+        .filter(|&(_, weight)| weight != 0)
+        .collect()
+}
+
+// Sorted (position, count) pairs
+fn group_counts(input: &[usize]) -> Vec<(usize, usize)> {
+    group_weights(input.iter().map(|&n| (n, 1)))
+}
+
+fn fuel_at(counts: &[(usize, usize)], x: usize, consumption: fn(usize) -> usize) -> usize {
+    counts.iter().fold(0, |sum, &(n, count)| sum + count * consumption(distance(x, n)))
+}
+
+// With a cost of |x - n|, moving x past a group changes the slope by twice its weight,
+// so the sum is flat exactly where the weight on either side is equal.
+fn median_alignment(groups: &[(usize, usize)]) -> Alignment {
+    let total: usize = groups.iter().map(|&(_, weight)| weight).sum();
+    let mut seen = 0;
+    for (i, &(n, weight)) in groups.iter().enumerate() {
+        seen += weight;
+        if 2 * seen >= total {
+            let high = if 2 * seen == total { groups[i + 1].0 } else { n };
+            return Alignment { positions: n..=high, fuel: fuel_at(groups, n, |d| d) }
+        }
     }
-    else {unreachable!()}
+    unreachable!()
+}
+
+// The triangular cost is (d^2 + d) / 2, whose sum is minimized within half a step of the mean.
+// Its second difference is positive, so there are at most two tied optimal positions.
+fn mean_alignment(groups: &[(usize, usize)]) -> Alignment {
+    let total: usize = groups.iter().map(|&(_, weight)| weight).sum();
+    let mean = groups.iter().map(|&(n, weight)| n * weight).sum::<usize>() / total;
+    let candidates = mean.saturating_sub(1)..=mean + 2;
+    let fuels: Vec<_> = candidates
+        .map(|x| (x, fuel_at(groups, x, triangular)))
+        .collect();
+    let fuel = fuels.iter().map(|&(_, fuel)| fuel).min().unwrap();
+    let (first, last) = match fuels.iter().filter(|&&(_, f)| f == fuel).minmax() {
+        MinMaxResult::OneElement(&(x, _)) => (x, x),
+        MinMaxResult::MinMax(&(a, _), &(b, _)) => (a, b),
+        MinMaxResult::NoElements => unreachable!(),
+    };
+    Alignment { positions: first..=last, fuel }
+}
+
+// Works for any convex consumption function. Convexity means the forward difference
+// f(x + 1) - f(x) never decreases, so we can binary search on its sign
+// (a ternary search that only needs one extra evaluation per step).
+fn convex_alignment(groups: &[(usize, usize)], consumption: fn(usize) -> usize) -> Alignment {
+    let f = |x| fuel_at(groups, x, consumption);
+    let (min, max) = (groups[0].0, groups[groups.len() - 1].0);
+    // first x where the function stops decreasing
+    let (mut lo, mut hi) = (min, max);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if f(mid) <= f(mid + 1) { hi = mid } else { lo = mid + 1 }
+    }
+    let first = lo;
+    // first x where the function starts increasing
+    hi = max;
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if f(mid) < f(mid + 1) { hi = mid } else { lo = mid + 1 }
+    }
+    Alignment { positions: first..=lo, fuel: f(first) }
+}
+
+#[derive(Clone, Copy)]
+enum Consumption {
+    Constant,
+    Linear,
+    Convex(fn(usize) -> usize),
+}
+
+impl Consumption {
+    fn cost(self) -> fn(usize) -> usize {
+        match self {
+            Consumption::Constant => |n| n,
+            Consumption::Linear => triangular,
+            Consumption::Convex(f) => f,
+        }
+    }
+
+    fn align(self, groups: &[(usize, usize)]) -> Alignment {
+        match self {
+            Consumption::Constant => median_alignment(groups),
+            Consumption::Linear => mean_alignment(groups),
+            Consumption::Convex(f) => convex_alignment(groups, f),
+        }
+    }
+}
+
+// Every allowed position that is optimal, in order, and their cost
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BoundedAlignment {
+    positions: Vec<usize>,
+    fuel: usize,
+}
+
+impl std::fmt::Display for BoundedAlignment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (at {})", self.fuel, self.positions.iter().join(", "))
+    }
+}
+
+// (position, weight) crabs with individual fuel efficiencies (a weight of 3 burns three
+// times the fuel), which may not end up at any of the forbidden positions.
+//
+// The cost stays convex, so the best allowed positions are either the allowed ones inside
+// the unconstrained optimum, or the closest allowed position on either side of it.
+fn bounded_alignment(
+    crabs: &[(usize, usize)],
+    forbidden: &HashSet<usize>,
+    consumption: Consumption
+) -> Option<BoundedAlignment> {
+    let groups = group_weights(crabs.iter().copied());
+    if groups.is_empty() {
+        return None
+    }
+    let best = consumption.align(&groups);
+    let (start, end) = (*best.positions.start(), *best.positions.end());
+    let allowed: Vec<_> = best.positions.filter(|x| !forbidden.contains(x)).collect();
+    if !allowed.is_empty() {
+        return Some(BoundedAlignment { positions: allowed, fuel: best.fuel })
+    }
+    // Everything between the two is forbidden
+    let left = (0..start).rev().find(|x| !forbidden.contains(x));
+    let right = (end + 1..).find(|x| !forbidden.contains(x));
+    let f = |x| fuel_at(&groups, x, consumption.cost());
+    match (left.map(|x| (x, f(x))), right.map(|x| (x, f(x)))) {
+        (Some((l, a)), Some((r, b))) if a == b => Some(BoundedAlignment { positions: vec![l, r], fuel: a }),
+        (Some((l, a)), Some((_, b))) if a < b => Some(BoundedAlignment { positions: vec![l], fuel: a }),
+        (_, Some((r, b))) => Some(BoundedAlignment { positions: vec![r], fuel: b }),
+        (Some((l, a)), None) => Some(BoundedAlignment { positions: vec![l], fuel: a }),
+        (None, None) => None,
+    }
+}
+
+// Every crab burning fuel at the same rate
+fn unit_weights(input: &[usize]) -> Vec<(usize, usize)> {
+    input.iter().map(|&n| (n, 1)).collect()
+}
+
+fn triangular(n: usize) -> usize {
+    n * (n + 1) / 2
 }
 
 #[aoc(day7, part1, Lazy)]
 fn constant_consumption(input: &[usize]) -> usize {
-    minimize_fuel_consumption(input, |n| n)
+    bounded_alignment(&unit_weights(input), &HashSet::new(), Consumption::Constant).unwrap().fuel
 }
 
 #[aoc(day7, part2, Lazy)]
 fn linear_consumption(input: &[usize]) -> usize {
-    minimize_fuel_consumption(input, |n| n * (n + 1) / 2)
+    bounded_alignment(&unit_weights(input), &HashSet::new(), Consumption::Linear).unwrap().fuel
+}
+
+#[aoc(day7, part1, Median)]
+fn constant_median(input: &[usize]) -> Alignment {
+    median_alignment(&group_counts(input))
+}
+
+#[aoc(day7, part2, Mean)]
+fn linear_mean(input: &[usize]) -> Alignment {
+    mean_alignment(&group_counts(input))
+}
+
+#[aoc(day7, part1, Convex)]
+fn constant_convex(input: &[usize]) -> Alignment {
+    Consumption::Convex(|n| n).align(&group_counts(input))
+}
+
+#[aoc(day7, part2, Convex)]
+fn linear_convex(input: &[usize]) -> Alignment {
+    Consumption::Convex(triangular).align(&group_counts(input))
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: [usize; 10] = [16, 1, 2, 0, 4, 2, 7, 1, 2, 14];
+
+    // Tries every position in the range
+    fn minimize_fuel_consumption(input: &[usize], consumption: fn(usize) -> usize) -> Alignment {
+        if let MinMaxResult::MinMax(&min, &max) = input.iter().minmax() {
+            let counts = group_counts(input);
+            let mut best = Alignment { positions: min..=min, fuel: usize::MAX };
+            for x in min..=max {
+                let fuel = fuel_at(&counts, x, consumption);
+                if fuel < best.fuel {
+                    best = Alignment { positions: x..=x, fuel };
+                }
+                else if fuel == best.fuel {
+                    best.positions = *best.positions.start()..=x;
+                }
+            }
+            best
+        }
+        else {unreachable!()}
+    }
+
+    #[test]
+    fn solvers_match_the_example() {
+        let groups = group_counts(&EXAMPLE);
+        assert_eq!(median_alignment(&groups), Alignment { positions: 2..=2, fuel: 37 });
+        assert_eq!(mean_alignment(&groups), Alignment { positions: 5..=5, fuel: 168 });
+        assert_eq!(convex_alignment(&groups, |n| n), median_alignment(&groups));
+        assert_eq!(convex_alignment(&groups, triangular), mean_alignment(&groups));
+        assert_eq!(constant_consumption(&EXAMPLE), 37);
+        assert_eq!(linear_consumption(&EXAMPLE), 168);
+    }
+
+    #[test]
+    fn solvers_match_brute_force() {
+        let inputs: [&[usize]; 4] = [&EXAMPLE, &[3, 3], &[1, 5], &[0, 0, 9, 9, 4, 100]];
+        for &input in &inputs {
+            let groups = group_counts(input);
+            assert_eq!(median_alignment(&groups), minimize_fuel_consumption(input, |n| n));
+            assert_eq!(mean_alignment(&groups), minimize_fuel_consumption(input, triangular));
+            assert_eq!(convex_alignment(&groups, |n| n * n), minimize_fuel_consumption(input, |n| n * n));
+        }
+    }
+
+    #[test]
+    fn bounded_positions_are_allowed_and_optimal() {
+        let crabs = [(1, 1), (5, 1)];
+        let forbidden: HashSet<_> = [2, 4].iter().copied().collect();
+        let best = bounded_alignment(&crabs, &forbidden, Consumption::Constant).unwrap();
+        assert_eq!(best, BoundedAlignment { positions: vec![1, 3, 5], fuel: 4 });
+
+        let forbidden: HashSet<_> = (1..=5).collect();
+        let best = bounded_alignment(&crabs, &forbidden, Consumption::Linear).unwrap();
+        assert_eq!(best, BoundedAlignment { positions: vec![0, 6], fuel: 16 });
+
+        let weighted = [(0, 3), (10, 1)];
+        let best = bounded_alignment(&weighted, &HashSet::new(), Consumption::Constant).unwrap();
+        assert_eq!(best, BoundedAlignment { positions: vec![0], fuel: 10 });
+        assert_eq!(bounded_alignment(&[], &HashSet::new(), Consumption::Constant), None);
+    }
+}
@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 use std::collections::HashMap;
 
 use itertools::Itertools;
@@ -68,24 +70,26 @@ fn actual_problem(input: &str) -> Vec<Row> {
         .collect()
 }
 
+// Lit segments a through g for each digit
+const DIGITS: [[bool; 7]; 10] = [
+    [true, true, true, false, true, true, true],
+    [false, false, true, false, false, true, false],
+    [true, false, true, true, true, false, true],
+    [true, false, true, true, false, true, true],
+    [false, true, true, true, false, true, false],
+    [true, true, false, true, false, true, true],
+    [true, true, false, true, true, true, true],
+    [true, false, true, false, false, true, false],
+    [true, true, true, true, true, true, true],
+    [true, true, true, true, false, true, true],
+];
+
 fn check_digit(digit: &[bool; 7], permutation: &[u8]) -> Option<u8> {
     let mut permuted = [false; 7];
     for i in 0..7 {
         permuted[i] = digit[permutation[i] as usize];
     }
-    match permuted {
-        [true, true, true, false, true, true, true] => Some(0),
-        [false, false, true, false, false, true, false] => Some(1),
-        [true, false, true, true, true, false, true] => Some(2),
-        [true, false, true, true, false, true, true] => Some(3),
-        [false, true, true, true, false, true, false] => Some(4),
-        [true, true, false, true, false, true, true] => Some(5),
-        [true, true, false, true, true, true, true] => Some(6),
-        [true, false, true, false, false, true, false] => Some(7),
-        [true, true, true, true, true, true, true] => Some(8),
-        [true, true, true, true, false, true, true] => Some(9),
-        _ => None
-    }
+    DIGITS.iter().position(|&d| d == permuted).map(|n| n as u8)
 }
 
 #[aoc(day8, part2, Bad)]
@@ -132,10 +136,10 @@ impl Row2 {
 }
 
 #[aoc(day8, part2, Good)]
-fn cool_boolean_statement(input: &[Row]) -> usize {
+fn cool_boolean_statement(input: &[Row]) -> Result<usize, DecodeError> {
     input
-        .into_iter()
-        .map(|row| Row2::from_row(row))
+        .iter()
+        .map(Row2::from_row)
         .map(|row| {
             // One pass to find knowns-by-count
             let mut permutation: HashMap<u8, u8> = HashMap::new();
@@ -172,14 +176,355 @@ fn cool_boolean_statement(input: &[Row]) -> usize {
                     (5, 1, 3) => {permutation.insert(hint, 5);},
                     (6, 2, 3) => {permutation.insert(hint, 0);},
                     (6, 2, 4) => {permutation.insert(hint, 9);},
-                    x => println!("{:?}", x)
+                    // Not a digit of any wiring
+                    _ => return Err(DecodeError::Unsolvable)
+                }
+            }
+            row.finals.iter().try_fold(0, |n, digit| match permutation.get(digit) {
+                Some(&d) => Ok(n * 10 + d as usize),
+                None => Err(DecodeError::Unsolvable)
+            })
+        })
+        .sum()
+}
+
+fn to_mask(segments: &[bool; 7]) -> u8 {
+    segments.iter().enumerate().fold(0, |mask, (i, &bit)| mask | (bit as u8) << i)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum DecodeError {
+    // The line isn't `patterns | patterns` over the letters a-g
+    Malformed(String),
+    // No wiring makes every pattern a digit
+    Unsolvable,
+    // More than one wiring makes every pattern a digit
+    Ambiguous,
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Malformed(reason) => write!(f, "malformed row: {}", reason),
+            DecodeError::Unsolvable => write!(f, "no wiring fits this row"),
+            DecodeError::Ambiguous => write!(f, "more than one wiring fits this row"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DecodedRow {
+    // wiring[w] is the real segment driven by wire w (both counted from a = 0)
+    wiring: [u8; 7],
+    digits: Vec<u8>,
+    value: usize,
+}
+
+fn parse_patterns(patterns: &str) -> Result<Vec<u8>, DecodeError> {
+    patterns
+        .split_whitespace()
+        .map(|pattern| {
+            let mut mask = 0u8;
+            for c in pattern.chars() {
+                let bit = match c {
+                    'a'..='g' => 1 << (c as u8 - b'a'),
+                    _ => return Err(DecodeError::Malformed(format!("unexpected character {:?}", c)))
+                };
+                if mask & bit != 0 {
+                    return Err(DecodeError::Malformed(format!("repeated wire in {:?}", pattern)))
                 }
+                mask |= bit;
             }
-            permutation[&row.finals[0]] as usize * 1000 +
-            permutation[&row.finals[1]] as usize * 100 +
-            permutation[&row.finals[2]] as usize * 10 +
-            permutation[&row.finals[3]] as usize
+            Ok(mask)
         })
-        .sum() 
+        .collect()
+}
+
+// Remove segments that are already pinned to one wire from every other wire
+fn propagate(domains: &mut [u8; 7]) -> bool {
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for w in 0..7 {
+            if domains[w] == 0 {
+                return false
+            }
+            if domains[w].count_ones() == 1 {
+                for other in 0..7 {
+                    if other != w && domains[other] & domains[w] != 0 {
+                        domains[other] &= !domains[w];
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+    true
+}
+
+fn apply_wiring(pattern: u8, wiring: &[u8; 7]) -> u8 {
+    (0..7).filter(|w| pattern & 1 << w != 0).fold(0, |mask, w| mask | 1 << wiring[w])
+}
+
+// Collects up to two complete wirings, which is enough to tell unique from ambiguous
+fn search(mut domains: [u8; 7], patterns: &[u8], digits: &[u8; 10], found: &mut Vec<[u8; 7]>) {
+    if found.len() > 1 || !propagate(&mut domains) {
+        return
+    }
+    let branch = (0..7)
+        .filter(|&w| domains[w].count_ones() > 1)
+        .min_by_key(|&w| domains[w].count_ones());
+    match branch {
+        Some(w) => {
+            for segment in 0..7 {
+                if domains[w] & 1 << segment != 0 {
+                    let mut next = domains;
+                    next[w] = 1 << segment;
+                    search(next, patterns, digits, found);
+                }
+            }
+        }
+        None => {
+            let mut wiring = [0u8; 7];
+            for w in 0..7 {
+                wiring[w] = domains[w].trailing_zeros() as u8;
+            }
+            if patterns.iter().all(|&p| digits.contains(&apply_wiring(p, &wiring))) {
+                found.push(wiring);
+            }
+        }
+    }
+}
+
+fn decode(line: &str) -> Result<DecodedRow, DecodeError> {
+    decode_with_font(line, &Font::STANDARD)
 }
 
+// Each pattern can only be a digit with the same number of segments, so its wires must
+// come from those digits' segments, and the wires outside it can't be any segment they
+// all share. That usually pins everything down, and a small search settles the rest.
+fn decode_with_font(line: &str, font: &Font) -> Result<DecodedRow, DecodeError> {
+    let mut iter = line.split('|');
+    let (hints, finals) = match (iter.next(), iter.next(), iter.next()) {
+        (Some(hints), Some(finals), None) => (parse_patterns(hints)?, parse_patterns(finals)?),
+        _ => return Err(DecodeError::Malformed("expected exactly one '|'".to_string()))
+    };
+    if finals.is_empty() {
+        return Err(DecodeError::Malformed("no output digits".to_string()))
+    }
+    let digits = font.masks();
+    let patterns: Vec<u8> = hints.iter().chain(finals.iter()).copied().collect();
+    let mut domains = [0x7fu8; 7];
+    for &pattern in &patterns {
+        let candidates: Vec<u8> = digits
+            .iter()
+            .copied()
+            .filter(|d| d.count_ones() == pattern.count_ones())
+            .collect();
+        let union = candidates.iter().fold(0, |a, &b| a | b);
+        let intersection = candidates.iter().fold(0x7f, |a, &b| a & b);
+        for (w, domain) in domains.iter_mut().enumerate() {
+            *domain &= if pattern & 1 << w != 0 { union } else { !intersection & 0x7f };
+        }
+    }
+    let mut found = Vec::new();
+    search(domains, &patterns, &digits, &mut found);
+    match found.len() {
+        0 => Err(DecodeError::Unsolvable),
+        1 => {
+            let wiring = found[0];
+            let digits: Vec<u8> = finals
+                .iter()
+                .map(|&p| {
+                    let lit = apply_wiring(p, &wiring);
+                    digits.iter().position(|&d| d == lit).unwrap() as u8
+                })
+                .collect();
+            let value = digits.iter().fold(0, |n, &d| n * 10 + d as usize);
+            Ok(DecodedRow { wiring, digits, value })
+        }
+        _ => Err(DecodeError::Ambiguous)
+    }
+}
+
+// Which segments each digit lights up, since real displays don't all agree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Font {
+    digits: [[bool; 7]; 10]
+}
+
+impl Font {
+    const STANDARD: Font = Font { digits: DIGITS };
+
+    // Lights or blanks one segment ('a' to 'g') of a digit, like a 7 without the f
+    // segment or a 9 without the bottom one
+    fn with_segment(mut self, digit: usize, segment: char, lit: bool) -> Self {
+        assert!(('a'..='g').contains(&segment), "no segment {:?}", segment);
+        self.digits[digit][(segment as u8 - b'a') as usize] = lit;
+        self
+    }
+
+    fn masks(&self) -> [u8; 10] {
+        let mut masks = [0u8; 10];
+        for (i, digit) in self.digits.iter().enumerate() {
+            masks[i] = to_mask(digit);
+        }
+        masks
+    }
+}
+
+fn pattern_string(segments: &[bool; 7], wiring: &[u8; 7]) -> String {
+    (0..7)
+        .filter(|&w| segments[wiring[w] as usize])
+        .map(|w| (b'a' + w as u8) as char)
+        .collect()
+}
+
+// The inverse of `decode_with_font`: every digit of the font as hints, then the given digits,
+// as seen through the wiring (wiring[w] is the real segment driven by wire w)
+fn encode(digits: &[u8], wiring: &[u8; 7], font: &Font) -> String {
+    let hints = font.digits.iter().map(|d| pattern_string(d, wiring)).join(" ");
+    let finals = digits.iter().map(|&d| pattern_string(&font.digits[d as usize], wiring)).join(" ");
+    format!("{} | {}", hints, finals)
+}
+
+#[aoc_generator(day8, part2, Propagation)]
+fn raw_rows(input: &str) -> Vec<String> {
+    input.lines().map(|line| line.to_string()).collect()
+}
+
+#[aoc(day8, part2, Propagation)]
+fn constraint_propagation(input: &[String]) -> Result<usize, DecodeError> {
+    input
+        .iter()
+        .map(|line| decode(line).map(|row| row.value))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xorshift(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    fn shuffle<T>(items: &mut [T], state: &mut u64) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, xorshift(state) as usize % (i + 1));
+        }
+    }
+
+    // Like `encode`, but with a random wiring, and the hints and the wires within each
+    // pattern shuffled too, the way the puzzle input looks
+    fn scrambled(digits: &[u8], font: &Font, seed: u64) -> (String, [u8; 7]) {
+        // xorshift never leaves zero, so nothing would get shuffled
+        assert_ne!(seed, 0, "the seed must not be zero");
+        let mut state = seed;
+        let mut wiring = [0, 1, 2, 3, 4, 5, 6];
+        shuffle(&mut wiring, &mut state);
+        let line = encode(digits, &wiring, font);
+        let mut scramble = |patterns: &str| -> Vec<String> {
+            patterns
+                .split_whitespace()
+                .map(|pattern| {
+                    let mut wires: Vec<char> = pattern.chars().collect();
+                    shuffle(&mut wires, &mut state);
+                    wires.into_iter().collect()
+                })
+                .collect()
+        };
+        let (hints, finals) = line.split_once(" | ").unwrap();
+        let mut hints = scramble(hints);
+        let finals = scramble(finals);
+        shuffle(&mut hints, &mut state);
+        (format!("{} | {}", hints.join(" "), finals.join(" ")), wiring)
+    }
+
+    const EXAMPLE: &str = "\
+be cfbegad cbdgef fgaecd cgeb fdcge agebfd fecdb fabcd edb | fdgacbe cefdb cefbgd gcbe
+edbfga begcd cbg gc gcadebf fbgde acbgfd abcde gfcbed gfec | fcgedb cgb dgebacf gc
+fgaebd cg bdaec gdafb agbcfd gdcbef bgcad gfac gcb cdgabef | cg cg fdcagb cbg
+fbegcd cbd adcefb dageb afcb bc aefdc ecdab fgdeca fcdbega | efabcd cedba gadfec cb
+aecbfdg fbg gf bafeg dbefa fcge gcbea fcaegb dgceab fcbdga | gecf egdcabf bgf bfgea
+fgeab ca afcebg bdacfeg cfaedg gcfdb baec bfadeg bafgc acf | gebdcfa ecba ca fadegcb
+dbcfg fgd bdegcaf fgec aegbdf ecdfab fbedc dacgb gdcebf gf | cefg dcbef fcge gbcadfe
+bdfegc cbegaf gecbf dfcage bdacg ed bedf ced adcbefg gebcd | ed bcgafe cdgba cbgef
+egadfb cdbfeg cegd fecab cgb gbdefca cg fgcdab egfdb bfceg | gbdfcae bgc cg cgb
+gcafb gcf dcaebfg ecagb gf abcdeg gaef cafbge fdbac fegbdc | fgae cfgab fg bagce";
+
+    #[test]
+    fn decoders_match_the_example() {
+        assert_eq!(cool_boolean_statement(&actual_problem(EXAMPLE)).unwrap(), 61229);
+        assert_eq!(constraint_propagation(&raw_rows(EXAMPLE)).unwrap(), 61229);
+        let row = decode("acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf").unwrap();
+        assert_eq!(row.digits, vec![5, 3, 5, 3]);
+        assert_eq!(row.wiring, [2, 5, 6, 0, 1, 3, 4]);
+    }
+
+    #[test]
+    fn decode_reports_bad_rows() {
+        assert!(matches!(decode("ab cd | ab"), Err(DecodeError::Unsolvable)));
+        assert!(matches!(decode("abx | ab"), Err(DecodeError::Malformed(_))));
+        assert!(matches!(decode("ab | ab | ab"), Err(DecodeError::Malformed(_))));
+        assert!(matches!(decode("ab | ab"), Err(DecodeError::Ambiguous)));
+        let unsolvable = "ab abc abcd cdefg abcdef abcdefg abd abe abf abg | ab ab ab ab";
+        assert!(matches!(cool_boolean_statement(&actual_problem(unsolvable)), Err(DecodeError::Unsolvable)));
+    }
+
+    #[test]
+    fn encode_inverts_decode() {
+        for line in EXAMPLE.lines() {
+            let row = decode(line).unwrap();
+            let plain = decode(&encode(&row.digits, &row.wiring, &Font::STANDARD)).unwrap();
+            assert_eq!(plain, row);
+        }
+    }
+
+    #[test]
+    fn scrambled_variant_fonts_decode_to_their_wiring() {
+        let fonts = [
+            Font::STANDARD,
+            Font::STANDARD.with_segment(7, 'f', false),
+            Font::STANDARD.with_segment(7, 'b', true),
+            Font::STANDARD.with_segment(9, 'g', false),
+            Font::STANDARD.with_segment(7, 'f', false).with_segment(9, 'g', false),
+        ];
+        for font in &fonts {
+            for seed in 1..20 {
+                let digits = [(seed % 10) as u8, 7, 9, 1];
+                let (line, wiring) = scrambled(&digits, font, seed);
+                let row = decode_with_font(&line, font).unwrap();
+                assert_eq!(row.wiring, wiring);
+                assert_eq!(row.digits, digits);
+            }
+        }
+    }
+
+    // Every example row shown again on a differently wired variant display
+    #[test]
+    fn example_rows_decode_on_a_variant_font() {
+        let font = Font::STANDARD.with_segment(7, 'f', false).with_segment(9, 'g', false);
+        let total: usize = EXAMPLE
+            .lines()
+            .enumerate()
+            .map(|(i, line)| {
+                let row = decode(line).unwrap();
+                let (line, _) = scrambled(&row.digits, &font, i as u64 + 1);
+                decode_with_font(&line, &font).unwrap().value
+            })
+            .sum();
+        assert_eq!(total, 61229);
+    }
+
+    #[test]
+    #[should_panic(expected = "the seed must not be zero")]
+    fn scrambling_needs_a_nonzero_seed() {
+        scrambled(&[1], &Font::STANDARD, 0);
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, VecDeque};
 
 #[aoc_generator(day9)]
 fn generator(input: &str) -> Vec<u32> {
@@ -79,4 +79,173 @@ fn basins(input: &[u32]) -> usize {
     basin_sizes.sort_unstable();
     let len = basin_sizes.len();
     basin_sizes[len - 1] * basin_sizes[len - 2] * basin_sizes[len - 3] 
-}
\ No newline at end of file
+}
+
+struct HeightMap {
+    width: usize,
+    height: usize,
+    data: Vec<u32>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Basin {
+    size: usize,
+    low_point: (usize, usize),
+    risk_level: u32
+}
+
+// labels[position] is the index of the basin the cell belongs to, or None for a 9
+struct BasinMap {
+    width: usize,
+    labels: Vec<Option<usize>>,
+    basins: Vec<Basin>
+}
+
+// One letter per basin (repeating after z), and 9s as blanks
+impl std::fmt::Display for BasinMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for row in self.labels.chunks(self.width) {
+            for label in row {
+                match label {
+                    Some(n) => write!(f, "{}", (b'a' + (n % 26) as u8) as char)?,
+                    None => write!(f, " ")?
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl HeightMap {
+    fn neighbors(&self, position: usize) -> impl Iterator<Item=usize> {
+        let (x, y) = (position % self.width, position / self.width);
+        let width = self.width;
+        [
+            (x != 0).then(|| position - 1),
+            (x != self.width - 1).then(|| position + 1),
+            (y != 0).then(|| position - width),
+            (y != self.height - 1).then(|| position + width),
+        ].into_iter().flatten()
+    }
+
+    fn is_low_point(&self, position: usize) -> bool {
+        self.neighbors(position).all(|n| self.data[n] > self.data[position])
+    }
+
+    // Breadth-first flood fill from every unlabeled cell, so basin size doesn't touch the stack
+    fn label_basins(&self) -> BasinMap {
+        let mut labels = vec![None; self.data.len()];
+        let mut basins = vec![];
+        let mut queue = VecDeque::new();
+        for start in 0..self.data.len() {
+            if self.data[start] == 9 || labels[start].is_some() {
+                continue;
+            }
+            let label = basins.len();
+            labels[start] = Some(label);
+            queue.push_back(start);
+            let (mut size, mut low) = (0, start);
+            while let Some(position) = queue.pop_front() {
+                size += 1;
+                if self.data[position] < self.data[low] {
+                    low = position;
+                }
+                for n in self.neighbors(position) {
+                    if self.data[n] != 9 && labels[n].is_none() {
+                        labels[n] = Some(label);
+                        queue.push_back(n);
+                    }
+                }
+            }
+            basins.push(Basin {
+                size,
+                low_point: (low % self.width, low / self.width),
+                risk_level: self.data[low] + 1
+            });
+        }
+        BasinMap { width: self.width, labels, basins }
+    }
+}
+
+#[aoc_generator(day9, part1, Labeled)]
+fn height_map(input: &str) -> HeightMap {
+    let lines: Vec<&str> = input.lines().collect();
+    HeightMap {
+        width: lines[0].len(),
+        height: lines.len(),
+        data: generator(input)
+    }
+}
+
+#[aoc(day9, part1, Labeled)]
+fn sum_low_points(input: &HeightMap) -> u32 {
+    (0..input.data.len())
+        .filter(|&pos| input.is_low_point(pos))
+        .map(|pos| input.data[pos] + 1)
+        .sum()
+}
+
+#[aoc_generator(day9, part2, Labeled)]
+fn height_map_again(input: &str) -> HeightMap {
+    height_map(input)
+}
+
+#[aoc(day9, part2, Labeled)]
+fn labeled_basins(input: &HeightMap) -> usize {
+    let mut sizes: Vec<_> = input.label_basins().basins.iter().map(|b| b.size).collect();
+    sizes.sort_unstable_by(|a, b| b.cmp(a));
+    sizes.iter().take(3).product()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+2199943210
+3987894921
+9856789892
+8767896789
+9899965678";
+
+    #[test]
+    fn example() {
+        let input = height_map(EXAMPLE);
+        assert_eq!(sum_low_points(&input), 15);
+        assert_eq!(labeled_basins(&input), 1134);
+    }
+
+    #[test]
+    fn example_basins() {
+        let basin = |size, low_point, risk_level| Basin { size, low_point, risk_level };
+        let map = height_map(EXAMPLE).label_basins();
+        assert_eq!(map.basins, vec![
+            basin(3, (1, 0), 2),
+            basin(9, (9, 0), 1),
+            basin(14, (2, 2), 6),
+            basin(9, (6, 4), 6),
+        ]);
+        assert_eq!(map.to_string(), "\
+aa   bbbbb
+a ccc b bb
+ ccccc d b
+ccccc ddd 
+ c   ddddd
+");
+    }
+
+    #[test]
+    fn non_square_grid() {
+        let input = height_map("21093\n99899");
+        assert_eq!((input.width, input.height), (5, 2));
+        assert_eq!(sum_low_points(&input), 5);
+        let map = input.label_basins();
+        assert_eq!(map.basins, vec![
+            Basin { size: 4, low_point: (2, 0), risk_level: 1 },
+            Basin { size: 1, low_point: (4, 0), risk_level: 4 },
+        ]);
+        assert_eq!(map.to_string(), "aaa b\n  a  \n");
+        assert_eq!(labeled_basins(&input), 4);
+    }
+}
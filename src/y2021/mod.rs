@@ -1,3 +1,5 @@
+mod cycle;
+mod ocr;
 mod d1;
 mod d2;
 mod d3;
@@ -0,0 +1,171 @@
+// Reads the block letters that some puzzles draw instead of printing an answer
+
+pub struct Font {
+    width: usize,
+    height: usize,
+    // Blank columns between letters
+    spacing: usize,
+    glyphs: &'static [(char, &'static str)]
+}
+
+pub const SMALL: Font = Font {
+    width: 4,
+    height: 6,
+    spacing: 1,
+    glyphs: &[
+        ('A', ".##.#..##..######..##..#"),
+        ('B', "###.#..####.#..##..####."),
+        ('C', ".##.#..##...#...#..#.##."),
+        ('E', "#####...###.#...#...####"),
+        ('F', "#####...###.#...#...#..."),
+        ('G', ".##.#..##...#.###..#.###"),
+        ('H', "#..##..######..##..##..#"),
+        ('I', ".###..#...#...#...#..###"),
+        ('J', "..##...#...#...##..#.##."),
+        ('K', "#..##.#.##..#.#.#.#.#..#"),
+        ('L', "#...#...#...#...#...####"),
+        ('O', ".##.#..##..##..##..#.##."),
+        ('P', "###.#..##..####.#...#..."),
+        ('R', "###.#..##..####.#.#.#..#"),
+        ('S', ".####...#....##....####."),
+        ('U', "#..##..##..##..##..#.##."),
+        ('Z', "####...#..#..#..#...####"),
+    ]
+};
+
+pub const LARGE: Font = Font {
+    width: 6,
+    height: 10,
+    spacing: 2,
+    glyphs: &[
+        ('A', "..##...#..#.#....##....##....########....##....##....##....#"),
+        ('B', "#####.#....##....##....######.#....##....##....##....######."),
+        ('C', ".####.#....##.....#.....#.....#.....#.....#.....#....#.####."),
+        ('E', "#######.....#.....#.....#####.#.....#.....#.....#.....######"),
+        ('F', "#######.....#.....#.....#####.#.....#.....#.....#.....#....."),
+        ('G', ".####.#....##.....#.....#.....#..####....##....##...##.###.#"),
+        ('H', "#....##....##....##....########....##....##....##....##....#"),
+        ('J', "...###....#.....#.....#.....#.....#.....#.#...#.#...#..###.."),
+        ('K', "#....##...#.#..#..#.#...##....##....#.#...#..#..#...#.#....#"),
+        ('L', "#.....#.....#.....#.....#.....#.....#.....#.....#.....######"),
+        ('N', "#....###...###...##.#..##.#..##..#.##..#.##...###...###....#"),
+        ('P', "#####.#....##....##....######.#.....#.....#.....#.....#....."),
+        ('R', "#####.#....##....##....######.#..#..#...#.#...#.#....##....#"),
+        ('X', "#....##....#.#..#..#..#...##....##...#..#..#..#.#....##....#"),
+        ('Z', "######.....#.....#....#....#....#....#....#.....#.....######"),
+    ]
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OcrError {
+    // Nothing is lit, or the letters are neither 6 nor 10 pixels tall
+    NoFont { height: usize },
+    // The left column of every letter that didn't match
+    Unrecognized { columns: Vec<usize>, partial: String },
+}
+
+impl std::fmt::Display for OcrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OcrError::NoFont { height } => write!(f, "no font is {} pixels tall", height),
+            OcrError::Unrecognized { columns, partial } => {
+                write!(f, "unrecognized letters at columns {:?} (read {:?})", columns, partial)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OcrError {}
+
+// Reads a row-major bitmap whose first letter starts in the top left corner,
+// with unknown letters as '?' in the partial result
+pub fn recognize(bits: &[bool], width: usize) -> Result<String, OcrError> {
+    let height = bits.len() / width;
+    let lit = |x: usize, y: usize| x < width && y < height && bits[y * width + x];
+    let bottom = (0..height).rev().find(|&y| (0..width).any(|x| lit(x, y)));
+    let right = (0..width).rev().find(|&x| (0..height).any(|y| lit(x, y)));
+    let (text_height, right) = match (bottom, right) {
+        (Some(bottom), Some(right)) => (bottom + 1, right),
+        _ => return Err(OcrError::NoFont { height: 0 })
+    };
+    let font = match text_height {
+        6 => SMALL,
+        10 => LARGE,
+        _ => return Err(OcrError::NoFont { height: text_height })
+    };
+    let mut text = String::new();
+    let mut unrecognized = vec![];
+    let mut x = 0;
+    while x <= right {
+        let glyph: String = (0..font.height)
+            .flat_map(|dy| (0..font.width).map(move |dx| (dx, dy)))
+            .map(|(dx, dy)| if lit(x + dx, dy) { '#' } else { '.' })
+            .collect();
+        match font.glyphs.iter().find(|&&(_, pattern)| pattern == glyph) {
+            Some(&(c, _)) => text.push(c),
+            None => {
+                text.push('?');
+                unrecognized.push(x);
+            }
+        }
+        x += font.width + font.spacing;
+    }
+    if unrecognized.is_empty() {
+        Ok(text)
+    }
+    else {
+        Err(OcrError::Unrecognized { columns: unrecognized, partial: text })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Draws the letters of a font side by side, as a bitmap `recognize` can read
+    fn draw(font: &Font, text: &str) -> (Vec<bool>, usize) {
+        let count = text.chars().count();
+        let width = count * (font.width + font.spacing) - font.spacing;
+        let mut bits = vec![false; width * font.height];
+        for (i, c) in text.chars().enumerate() {
+            let &(_, pattern) = font.glyphs.iter().find(|&&(g, _)| g == c).unwrap();
+            for (j, pixel) in pattern.chars().enumerate() {
+                let (x, y) = (i * (font.width + font.spacing) + j % font.width, j / font.width);
+                bits[y * width + x] = pixel == '#';
+            }
+        }
+        (bits, width)
+    }
+
+    #[test]
+    fn glyph_tables_are_complete() {
+        for font in &[SMALL, LARGE] {
+            for &(c, pattern) in font.glyphs {
+                assert_eq!(pattern.len(), font.width * font.height, "{:?}", c);
+            }
+        }
+    }
+
+    #[test]
+    fn reads_every_letter() {
+        for font in &[SMALL, LARGE] {
+            let text: String = font.glyphs.iter().map(|&(c, _)| c).collect();
+            let (bits, width) = draw(font, &text);
+            assert_eq!(recognize(&bits, width), Ok(text));
+        }
+    }
+
+    #[test]
+    fn reports_unknown_letters_and_sizes() {
+        let (mut bits, width) = draw(&SMALL, "AHA");
+        // Fill in the middle of the H
+        bits[width + 6] = true;
+        bits[width + 7] = true;
+        assert_eq!(
+            recognize(&bits, width),
+            Err(OcrError::Unrecognized { columns: vec![5], partial: "A?A".to_string() })
+        );
+        assert_eq!(recognize(&[false; 12], 4), Err(OcrError::NoFont { height: 0 }));
+        assert_eq!(recognize(&[true; 12], 4), Err(OcrError::NoFont { height: 3 }));
+    }
+}